@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::iter;
+use std::path::PathBuf;
+use crossterm::event::{ KeyCode, KeyEvent, KeyModifiers };
+use crate::{ LineNumbers, QUIT_TIMES, TAB_STOP };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeySpec {
+    pub modifiers: KeyModifiers,
+    pub code: KeyCode
+}
+
+impl KeySpec {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = spec;
+        while let Some((prefix, tail)) = rest.split_once('-').filter(|(_, tail)| !tail.is_empty()) {
+            modifiers |= match prefix.to_ascii_lowercase().as_str() {
+                "c" | "ctrl" => KeyModifiers::CONTROL,
+                "a" | "m" | "alt" => KeyModifiers::ALT,
+                "s" | "shift" => KeyModifiers::SHIFT,
+                _ => return None
+            };
+            rest = tail;
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next()?, chars.next()) {
+            (ch, None) => KeyCode::Char(ch),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "esc" => KeyCode::Esc,
+                "enter" | "cr" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "space" => KeyCode::Char(' '),
+                "backspace" | "bs" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|number| number.parse().ok()) {
+                    Some(number @ 1..=12) => KeyCode::F(number),
+                    _ => return None
+                }
+            }
+        };
+        Some(Self::new(code, modifiers))
+    }
+
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let modifiers = match code {
+            KeyCode::Char(_) => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers
+        };
+        let code = match code {
+            KeyCode::Char(ch) if modifiers.contains(KeyModifiers::CONTROL) => {
+                KeyCode::Char(ch.to_ascii_lowercase())
+            }
+            _ => code
+        };
+        Self { modifiers, code }
+    }
+
+    pub fn name(&self) -> String {
+        let key = match self.code {
+            KeyCode::Char(' ') => "space".into(),
+            KeyCode::Char(ch) => ch.to_string(),
+            KeyCode::Esc => "esc".into(),
+            KeyCode::Enter => "enter".into(),
+            KeyCode::Tab => "tab".into(),
+            KeyCode::Backspace => "backspace".into(),
+            KeyCode::Delete => "delete".into(),
+            KeyCode::Up => "up".into(),
+            KeyCode::Down => "down".into(),
+            KeyCode::Left => "left".into(),
+            KeyCode::Right => "right".into(),
+            KeyCode::Home => "home".into(),
+            KeyCode::End => "end".into(),
+            KeyCode::PageUp => "pageup".into(),
+            KeyCode::PageDown => "pagedown".into(),
+            KeyCode::F(number) => format!("f{}", number),
+            code => format!("{:?}", code).to_ascii_lowercase()
+        };
+        let prefixes = [
+            (KeyModifiers::CONTROL, "C-"),
+            (KeyModifiers::ALT, "A-"),
+            (KeyModifiers::SHIFT, "S-")
+        ];
+        prefixes
+            .iter()
+            .filter(|(modifier, _)| self.modifiers.contains(*modifier))
+            .map(|(_, prefix)| *prefix)
+            .chain(iter::once(key.as_str()))
+            .collect()
+    }
+
+    fn is_typed(&self) -> bool {
+        matches!(self.code, KeyCode::Char(_))
+            && !self.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+    }
+}
+
+impl From<KeyEvent> for KeySpec {
+    fn from(key: KeyEvent) -> Self {
+        Self::new(key.code, key.modifiers)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Save,
+    Left,
+    Down,
+    Up,
+    Right,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    Help
+}
+
+impl Action {
+    pub const ALL: [Action; 12] = [
+        Action::Quit,
+        Action::Save,
+        Action::Left,
+        Action::Down,
+        Action::Up,
+        Action::Right,
+        Action::WordForward,
+        Action::WordBackward,
+        Action::WordEnd,
+        Action::LineStart,
+        Action::LineEnd,
+        Action::Help
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "quit" => Some(Action::Quit),
+            "save" => Some(Action::Save),
+            "left" => Some(Action::Left),
+            "down" => Some(Action::Down),
+            "up" => Some(Action::Up),
+            "right" => Some(Action::Right),
+            "word_forward" => Some(Action::WordForward),
+            "word_backward" => Some(Action::WordBackward),
+            "word_end" => Some(Action::WordEnd),
+            "line_start" => Some(Action::LineStart),
+            "line_end" => Some(Action::LineEnd),
+            "help" => Some(Action::Help),
+            _ => None
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "quit the editor",
+            Action::Save => "save the current buffer",
+            Action::Left => "move left",
+            Action::Down => "move down",
+            Action::Up => "move up",
+            Action::Right => "move right",
+            Action::WordForward => "move to the start of the next word",
+            Action::WordBackward => "move to the start of the previous word",
+            Action::WordEnd => "move to the end of the word",
+            Action::LineStart => "move to the start of the line",
+            Action::LineEnd => "move to the end of the line",
+            Action::Help => "show this help"
+        }
+    }
+
+    pub fn default_key(self) -> KeyEvent {
+        let (code, modifiers) = match self {
+            Action::Quit => (KeyCode::Char('q'), KeyModifiers::CONTROL),
+            Action::Save => (KeyCode::Char('s'), KeyModifiers::CONTROL),
+            Action::Left => (KeyCode::Char('h'), KeyModifiers::NONE),
+            Action::Down => (KeyCode::Char('j'), KeyModifiers::NONE),
+            Action::Up => (KeyCode::Char('k'), KeyModifiers::NONE),
+            Action::Right => (KeyCode::Char('l'), KeyModifiers::NONE),
+            Action::WordForward => (KeyCode::Char('w'), KeyModifiers::NONE),
+            Action::WordBackward => (KeyCode::Char('b'), KeyModifiers::NONE),
+            Action::WordEnd => (KeyCode::Char('e'), KeyModifiers::NONE),
+            Action::LineStart => (KeyCode::Char('0'), KeyModifiers::NONE),
+            Action::LineEnd => (KeyCode::Char('$'), KeyModifiers::NONE),
+            Action::Help => (KeyCode::F(1), KeyModifiers::NONE)
+        };
+        KeyEvent::new(code, modifiers)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListChars {
+    pub tab: Option<(char, char)>,
+    pub trail: Option<char>,
+    pub eol: Option<char>
+}
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            tab: Some(('\u{203a}', ' ')),
+            trail: Some('\u{b7}'),
+            eol: None
+        }
+    }
+}
+
+impl ListChars {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut listchars = Self {
+            tab: None,
+            trail: None,
+            eol: None
+        };
+        for item in spec.split(',').filter(|item| !item.is_empty()) {
+            let (name, value) = item.split_once(':')?;
+            let mut chars = value.chars();
+            match (name, chars.next(), chars.next(), chars.next()) {
+                ("tab", Some(first), Some(fill), None) => listchars.tab = Some((first, fill)),
+                ("trail", Some(trail), None, None) => listchars.trail = Some(trail),
+                ("eol", Some(eol), None, None) => listchars.eol = Some(eol),
+                _ => return None
+            }
+        }
+        Some(listchars)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub tab_width: usize,
+    pub shift_width: usize,
+    pub expand_tab: bool,
+    pub auto_indent: bool,
+    pub autopairs: bool,
+    pub regex: bool,
+    pub list: bool,
+    pub wrap: bool,
+    pub cursor_line: bool,
+    pub ruler: bool,
+    pub color_columns: Vec<usize>,
+    pub listchars: ListChars,
+    pub line_numbers: LineNumbers,
+    pub quit_times: u8,
+    pub scroll_step: usize,
+    pub scroll_off: usize,
+    pub autosave: u64,
+    pub trim_trailing_whitespace: bool,
+    pub final_newline: bool,
+    pub bindings: HashMap<KeySpec, Action>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: TAB_STOP,
+            shift_width: 0,
+            expand_tab: false,
+            auto_indent: false,
+            autopairs: false,
+            regex: false,
+            list: false,
+            wrap: false,
+            cursor_line: false,
+            ruler: false,
+            color_columns: Vec::new(),
+            listchars: ListChars::default(),
+            line_numbers: LineNumbers::Off,
+            quit_times: QUIT_TIMES,
+            scroll_step: 3,
+            scroll_off: 0,
+            autosave: 0,
+            trim_trailing_whitespace: false,
+            final_newline: false,
+            bindings: HashMap::new()
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("rusty-vim").join("config.toml"))
+    }
+
+    pub fn load() -> (Self, Vec<String>) {
+        match Self::path().map(fs::read_to_string) {
+            Some(Ok(contents)) => Self::parse(&contents),
+            Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => {
+                (Self::default(), vec![format!("Could not read config: {}", err)])
+            }
+            _ => (Self::default(), Vec::new())
+        }
+    }
+
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+        let mut section = "";
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.split_once('=') {
+                _ if line.starts_with('[') => match strip_comment(line) {
+                    "[keys]" => {
+                        section = "keys";
+                        Ok(())
+                    }
+                    other => Err(format!("unknown section `{}`", other))
+                },
+                Some((key, value)) => {
+                    let value = strip_comment(value.trim());
+                    match section {
+                        "keys" => config.bind(unquote(key.trim()), unquote(value)),
+                        _ => config.set(key.trim(), value)
+                    }
+                }
+                None => Err("expected `key = value`".into())
+            };
+            if let Err(err) = result {
+                errors.push(format!("config line {}: {}", number + 1, err));
+            }
+        }
+        (config, errors)
+    }
+
+    pub fn translate(&self, key: KeyEvent, typing: bool) -> KeyEvent {
+        let spec = KeySpec::from(key);
+        match self.bindings.get(&spec) {
+            Some(action) if !(typing && spec.is_typed()) => action.default_key(),
+            _ => key
+        }
+    }
+
+    pub fn help(&self) -> Vec<String> {
+        Action::ALL
+            .iter()
+            .map(|&action| {
+                let default = KeySpec::from(action.default_key());
+                let mut keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|&(_, &bound)| bound == action)
+                    .map(|(key, _)| key.name())
+                    .collect();
+                keys.sort();
+                if !self.bindings.contains_key(&default) {
+                    keys.insert(0, default.name());
+                }
+                format!("{:<16}{}", keys.join(" "), action.description())
+            })
+            .collect()
+    }
+
+    fn bind(&mut self, spec: &str, action: &str) -> Result<(), String> {
+        let key = KeySpec::parse(spec).ok_or_else(|| format!("invalid key `{}`", spec))?;
+        let action = Action::parse(action).ok_or_else(|| format!("unknown action `{}`", action))?;
+        self.bindings.insert(key, action);
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "tab_width" => {
+                self.tab_width = match value.parse() {
+                    Ok(0) | Err(_) => return Err(format!("invalid tab_width `{}`", value)),
+                    Ok(tab_width) => tab_width
+                }
+            }
+            "shift_width" => {
+                self.shift_width = value
+                    .parse()
+                    .map_err(|_| format!("invalid shift_width `{}`", value))?
+            }
+            "expand_tab" => self.expand_tab = parse_bool(key, value)?,
+            "auto_indent" => self.auto_indent = parse_bool(key, value)?,
+            "autopairs" => self.autopairs = parse_bool(key, value)?,
+            "regex" => self.regex = parse_bool(key, value)?,
+            "list" => self.list = parse_bool(key, value)?,
+            "wrap" => self.wrap = parse_bool(key, value)?,
+            "cursor_line" => self.cursor_line = parse_bool(key, value)?,
+            "ruler" => self.ruler = parse_bool(key, value)?,
+            "color_column" => {
+                self.color_columns = parse_columns(unquote(value))
+                    .ok_or_else(|| format!("invalid color_column `{}`", value))?
+            }
+            "listchars" => {
+                self.listchars = ListChars::parse(unquote(value))
+                    .ok_or_else(|| format!("invalid listchars `{}`", value))?
+            }
+            "line_numbers" => {
+                self.line_numbers = match unquote(value) {
+                    "off" => LineNumbers::Off,
+                    "absolute" => LineNumbers::Absolute,
+                    "relative" => LineNumbers::Relative,
+                    "hybrid" => LineNumbers::Hybrid,
+                    other => return Err(format!("invalid line_numbers `{}`", other))
+                }
+            }
+            "quit_times" => {
+                self.quit_times = value
+                    .parse()
+                    .map_err(|_| format!("invalid quit_times `{}`", value))?
+            }
+            "scroll_step" => {
+                self.scroll_step = value
+                    .parse()
+                    .map_err(|_| format!("invalid scroll_step `{}`", value))?
+            }
+            "scroll_off" => {
+                self.scroll_off = value
+                    .parse()
+                    .map_err(|_| format!("invalid scroll_off `{}`", value))?
+            }
+            "trim_trailing_whitespace" => {
+                self.trim_trailing_whitespace = parse_bool(key, value)?
+            }
+            "final_newline" => self.final_newline = parse_bool(key, value)?,
+            "autosave" => {
+                self.autosave = value
+                    .parse()
+                    .map_err(|_| format!("invalid autosave `{}`", value))?
+            }
+            _ => return Err(format!("unknown option `{}`", key))
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_columns(value: &str) -> Option<Vec<usize>> {
+    value
+        .split(',')
+        .filter(|column| !column.is_empty())
+        .map(|column| column.trim().parse().ok().filter(|&column| column > 0))
+        .collect()
+}
+
+fn strip_comment(value: &str) -> &str {
+    match value.starts_with('"') {
+        true => value[1..].find('"').map_or(value, |end| &value[..end + 2]),
+        false => value.split('#').next().unwrap_or_default().trim_end()
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("invalid {} `{}`", key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sample_config_with_defaults() {
+        let (config, errors) = Config::parse(
+            "# editor settings\n\
+             tab_width = 4\n\
+             expand_tab = true # spaces\n\
+             \n\
+             line_numbers = \"relative\"\n"
+        );
+        assert!(errors.is_empty());
+        assert_eq!(config.tab_width, 4);
+        assert!(config.expand_tab);
+        assert_eq!(config.line_numbers, LineNumbers::Relative);
+        assert_eq!(config.quit_times, QUIT_TIMES);
+        assert_eq!(Config::parse(""), (Config::default(), Vec::new()));
+    }
+
+    #[test]
+    fn report_invalid_config_lines() {
+        let (config, errors) = Config::parse("tab_width = 0\nfoo = 1\nexpand_tab = yes\nquit_times = 2");
+        assert_eq!(
+            errors,
+            [
+                "config line 1: invalid tab_width `0`",
+                "config line 2: unknown option `foo`",
+                "config line 3: invalid expand_tab `yes`"
+            ]
+        );
+        assert_eq!(config.tab_width, TAB_STOP);
+        assert_eq!(config.quit_times, 2);
+    }
+
+    #[test]
+    fn parse_key_specs() {
+        let ctrl_q = KeySpec::parse("Ctrl-q").unwrap();
+        assert_eq!(ctrl_q, KeySpec { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('q') });
+        assert_eq!(KeySpec::parse("C-Q"), Some(ctrl_q));
+        assert_eq!(KeySpec::parse("j").unwrap().code, KeyCode::Char('j'));
+        assert_eq!(KeySpec::parse("-").unwrap().code, KeyCode::Char('-'));
+        assert_eq!(KeySpec::parse("pagedown").unwrap().code, KeyCode::PageDown);
+        assert_eq!(KeySpec::parse("Hyper-x"), None);
+        assert_eq!(KeySpec::parse("banana"), None);
+    }
+
+    #[test]
+    fn bindings_translate_keys() {
+        let (config, errors) = Config::parse("[keys]\n\"C-x\" = \"quit\"\nn = \"down\"\nz = \"fly\"");
+        assert_eq!(errors, ["config line 4: unknown action `fly`"]);
+        let ctrl_x = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::CONTROL);
+        assert_eq!(config.translate(ctrl_x, false), Action::Quit.default_key());
+        let n = KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE);
+        assert_eq!(config.translate(n, false), Action::Down.default_key());
+        assert_eq!(config.translate(n, true), n);
+        assert_eq!(config.translate(ctrl_x, true), Action::Quit.default_key());
+    }
+
+    #[test]
+    fn help_lists_core_and_remapped_bindings() {
+        let help = Config::default().help();
+        assert!(help.contains(&format!("{:<16}quit the editor", "C-q")));
+        assert!(help.contains(&format!("{:<16}move down", "j")));
+        assert!(help.contains(&format!("{:<16}move to the end of the line", "$")));
+        assert!(help.contains(&format!("{:<16}show this help", "f1")));
+        assert_eq!(help.len(), Action::ALL.len());
+        let (config, errors) = Config::parse("[keys]\n\"C-x\" = \"quit\"\nn = \"down\"\nF2 = \"help\"");
+        assert!(errors.is_empty(), "{:?}", errors);
+        let help = config.help();
+        assert!(help.contains(&format!("{:<16}quit the editor", "C-q C-x")));
+        assert!(help.contains(&format!("{:<16}move down", "j n")));
+        assert!(help.contains(&format!("{:<16}show this help", "f1 f2")));
+        assert_eq!(KeySpec::parse("f13"), None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+
+pub fn files(root: &Path, limit: usize) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries: Vec<_> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut dirs = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dirs.push(path),
+                Ok(_) => {
+                    let path = path.strip_prefix(root).unwrap_or(&path);
+                    files.push(path.to_string_lossy().into_owned());
+                    if files.len() >= limit {
+                        return files;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        pending.extend(dirs.into_iter().rev());
+    }
+    files
+}
+
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for query_ch in query.chars().filter(|ch| !ch.is_whitespace()) {
+        let index = (next..candidate.len())
+            .find(|&index| candidate[index].to_lowercase().eq(query_ch.to_lowercase()))?;
+        score += 1;
+        match previous {
+            Some(previous) if previous + 1 == index => score += 10,
+            Some(previous) => score -= gap_penalty(index - previous),
+            None => score -= gap_penalty(index)
+        }
+        if index == 0 || "/\\_-. ".contains(candidate[index - 1]) {
+            score += 8;
+        }
+        previous = Some(index);
+        next = index + 1;
+    }
+    Some(score)
+}
+
+pub fn rank(query: &str, candidates: &[String], limit: usize) -> Vec<usize> {
+    let mut scored: Vec<(i64, usize)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| score(query, candidate).map(|score| (score, index)))
+        .collect();
+    scored.sort_by_key(|&(score, index)| (Reverse(score), candidates[index].len(), index));
+    scored.into_iter().take(limit).map(|(_, index)| index).collect()
+}
+
+pub fn summary(candidates: &[String], matches: &[usize], selected: usize) -> String {
+    if matches.is_empty() {
+        return "  (no matches)".into();
+    }
+    matches
+        .iter()
+        .enumerate()
+        .map(|(position, &index)| match position == selected {
+            true => format!("  [{}]", candidates[index]),
+            false => format!("  {}", candidates[index])
+        })
+        .collect()
+}
+
+fn gap_penalty(gap: usize) -> i64 {
+    gap.min(5) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn score_requires_subsequence() {
+        assert_eq!(score("xyz", "src/main.rs"), None);
+        assert_eq!(score("nm", "main"), None);
+        assert!(score("MAIN", "src/main.rs").is_some());
+        assert!(score("main", "src/main.rs") > score("main", "src/my_domain.rs"));
+        assert!(score("sm", "src/main.rs") > score("sm", "cosmos.rs"));
+    }
+
+    #[test]
+    fn rank_orders_by_score_then_length() {
+        let paths = candidates(&[
+            "README.md",
+            "tests/main_test.rs",
+            "src/syntax.rs",
+            "src/main.rs",
+            "src/make/input.rs"
+        ]);
+        assert_eq!(rank("main", &paths, 10), [3, 1, 4]);
+        assert_eq!(rank("main", &paths, 1), [3]);
+        assert_eq!(rank("", &paths, 2), [0, 3]);
+    }
+
+    #[test]
+    fn summary_marks_selected_match() {
+        let paths = candidates(&["a.rs", "b.rs", "c.rs"]);
+        assert_eq!(summary(&paths, &[2, 0], 1), "  c.rs  [a.rs]");
+        assert_eq!(summary(&paths, &[], 0), "  (no matches)");
+    }
+
+    #[test]
+    fn files_skips_hidden_entries_and_caps_count() {
+        let name = format!("rusty-vim-{}-finder", std::process::id());
+        let root = std::env::temp_dir().join(name);
+        for dir in ["src", ".git", "docs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let names = ["src/main.rs", "src/lib.rs", ".git/HEAD", ".hidden", "docs/guide.md", "top.txt"];
+        for file in names {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let found = files(&root, 100);
+        assert_eq!(found, ["top.txt", "docs/guide.md", "src/lib.rs", "src/main.rs"]);
+        assert_eq!(files(&root, 2).len(), 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
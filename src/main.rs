@@ -1,216 +1,7147 @@
-use std::io::{self, stdout, Write};
-use std::time::Duration;
-use crossterm::{ event, terminal, execute, cursor, queue };
-use crossterm::event::{ Event, KeyCode, KeyEvent };
+mod config;
+mod finder;
+mod positions;
+mod search;
+mod swap;
+mod syntax;
+mod tags;
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, stdout, IsTerminal, Write};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use crossterm::{ event, terminal, execute, cursor, queue, style, Command as _ };
+use crossterm::event::{ Event, KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind };
+use config::{ Config, ListChars };
+use positions::Positions;
+use search::Pattern;
+use syntax::{ HighlightType, NoHighlight, SyntaxHighlight };
+use unicode_width::UnicodeWidthChar;
+
+const QUIT_TIMES: u8 = 3;
+const TAB_STOP: usize = 8;
+const JUMP_LIST_SIZE: usize = 100;
+const WINDOW_PREFIX: char = '\u{17}';
+const READ_ONLY_MESSAGE: &str = "E45: 'readonly' option is set";
+const CURSOR_LINE_BACKGROUND: style::Color = style::Color::AnsiValue(236);
+const COLOR_COLUMN_BACKGROUND: style::Color = style::Color::DarkRed;
+const MAX_MACRO_DEPTH: usize = 100;
+const FIND_LIMIT: usize = 10_000;
+const FIND_MATCHES: usize = 5;
+const SWAP_DELAY: Duration = Duration::from_secs(4);
+const HELP_NAME: &str = "[Help]";
+
+type PromptCallback<'a> = dyn FnMut(&mut Output, &str, KeyEvent) + 'a;
 
 struct CleanUp;
 
 impl Drop for CleanUp {
     fn drop(&mut self) {
-        terminal::disable_raw_mode().expect("Couldn't switch back to canonical mode");
-        Output::clear_screeen().expect("Error");
+        if let Err(err) = execute!(
+            stdout(),
+            event::DisableFocusChange,
+            event::DisableMouseCapture,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        ) {
+            eprintln!("Couldn't restore the terminal screen: {}", err);
+        }
+        if let Err(err) = terminal::disable_raw_mode() {
+            eprintln!("Couldn't switch back to canonical mode: {}", err);
+        }
     }
 }
 
 struct Output {
     win_size: (usize, usize),
     editor_contents: EditorContents,
-    cursor_controller: CursorController
+    cursor_controller: CursorController,
+    editor_rows: EditorRows,
+    status_message: StatusMessage,
+    search_state: SearchState,
+    jump_list: JumpList,
+    tag_stack: Vec<(usize, (usize, usize))>,
+    line_numbers: LineNumbers,
+    visual_anchor: Option<(usize, usize)>,
+    config: Config,
+    buffers: Vec<Buffer>,
+    current_buffer: usize,
+    alternate_buffer: Option<usize>,
+    windows: Vec<Window>,
+    current_window: usize,
+    split: Split,
+    needs_redraw: bool,
+    prompt_hint: String,
+    bracket_match: Option<(usize, usize)>,
+    positions_file: Option<PathBuf>
 }
 
 impl Output {
-    fn new() -> Self {
+    fn new(editor_rows: EditorRows, config: Config) -> Self {
         let win_size = terminal::size()
-            .map(|(x, y)| (x as usize, y as usize))
+            .map(|(x, y)| Self::window_size(x, y))
             .unwrap();
-        Self { 
+        let mut output = Self::with_size(editor_rows, config, win_size);
+        output.positions_file = Positions::path();
+        output.restore_position();
+        output
+    }
+
+    fn with_size(mut editor_rows: EditorRows, config: Config, win_size: (usize, usize)) -> Self {
+        editor_rows.set_tab_stop(config.tab_width);
+        let mut output = Self {
             win_size,
             editor_contents: EditorContents::new(),
-            cursor_controller: CursorController::new()
+            cursor_controller: CursorController::new(win_size),
+            editor_rows,
+            status_message: StatusMessage::new("HELP: Ctrl-Q = quit | F1 = help".into()),
+            search_state: SearchState::new(),
+            jump_list: JumpList::new(),
+            tag_stack: Vec::new(),
+            line_numbers: config.line_numbers,
+            visual_anchor: None,
+            config,
+            buffers: vec![Buffer::new(EditorRows::new(), win_size)],
+            current_buffer: 0,
+            alternate_buffer: None,
+            windows: vec![Window::new(0, win_size)],
+            current_window: 0,
+            split: Split::Horizontal,
+            needs_redraw: true,
+            prompt_hint: String::new(),
+            bracket_match: None,
+            positions_file: None
+        };
+        output.layout_windows();
+        if let Some(path) = &output.editor_rows.filename {
+            if !path.exists() {
+                let message = format!("\"{}\" [New]", path.display());
+                output.set_message(&message);
+            }
         }
+        output.offer_recovery();
+        output
     }
 
-    fn clear_screeen() -> crossterm::Result<()> {
-        execute!(stdout(), terminal::Clear(terminal::ClearType::All))?;
-        execute!(stdout(), cursor::MoveTo(0, 0))
+    fn offer_recovery(&mut self) {
+        if !self.editor_rows.swap_found {
+            return;
+        }
+        if let Some(path) = &self.editor_rows.filename {
+            let message = format!(
+                "Found swap file \"{}\"; :recover to restore unsaved changes",
+                swap::path(path).display()
+            );
+            self.set_message(&message);
+        }
     }
 
-    fn move_cursor(&mut self, direction: char) {
-       self.cursor_controller.move_cursor(direction); 
+    fn recover(&mut self) {
+        let name = self.editor_rows.display_name();
+        match self.editor_rows.recover() {
+            Ok(()) => {
+                let cursor = (self.cursor_controller.cursor_y, self.cursor_controller.cursor_x);
+                self.set_cursor_position(cursor);
+                self.set_message(&format!("Recovered \"{}\"; :w to keep the changes", name));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.set_message(&format!("E305: No swap file found for {}", name))
+            }
+            Err(err) => self.set_message(&format!("Can't recover {}: {}", name, err))
+        }
     }
 
-    fn draw_rows(&mut self) {
-        let screen_rows = self.win_size.1;
-        let screen_columns = self.win_size.0;
-        for i in 0..screen_rows {
-            if i == screen_rows / 3 {
-                let mut welcome = format!("Rusty vim --- Version 0.1.1");
-                if welcome.len() > screen_columns {
-                    welcome.truncate(screen_columns)
-                }
-                let mut padding = (screen_columns - welcome.len()) / 2;
-                if padding != 0 {
-                    self.editor_contents.push('~');
-                    padding -= 1
-                }
-                (0..padding).for_each(|_| self.editor_contents.push(' '));
-                self.editor_contents.push_str(&welcome);
-            } else {
-                self.editor_contents.push('~');
+    fn write_swap_files(&mut self) {
+        for index in 0..self.buffers.len() {
+            if let Err(err) = self.buffer_rows_mut(index).write_swap() {
+                let name = self.buffer_name(index);
+                self.set_message(&format!(
+                    "E303: Unable to open swap file for \"{}\": {}",
+                    name, err
+                ));
+                self.needs_redraw = true;
             }
-            queue!(
-                self.editor_contents,
-                terminal::Clear(terminal::ClearType::UntilNewLine)
-                )
-                .unwrap();
-            if i < screen_rows - 1 {
-                self.editor_contents.push_str("\r\n");
+        }
+    }
+
+    fn remove_swap_files(&mut self) {
+        for index in 0..self.buffers.len() {
+            let _ = self.buffer_rows_mut(index).remove_swap();
+        }
+    }
+
+    fn restore_position(&mut self) {
+        let Some(positions_file) = &self.positions_file else {
+            return;
+        };
+        let file = self
+            .editor_rows
+            .filename
+            .as_deref()
+            .and_then(|path| fs::canonicalize(path).ok());
+        if let Some(position) = file.and_then(|file| Positions::load_from(positions_file).get(&file)) {
+            self.set_cursor_position(position);
+        }
+    }
+
+    fn remember_positions(&self) -> io::Result<()> {
+        let Some(positions_file) = &self.positions_file else {
+            return Ok(());
+        };
+        let mut positions = Positions::load_from(positions_file);
+        for index in 0..self.buffers.len() {
+            let cursor_controller = match index == self.current_buffer {
+                true => &self.cursor_controller,
+                false => &self.buffers[index].cursor_controller
+            };
+            let file = self
+                .buffer_rows(index)
+                .filename
+                .as_deref()
+                .and_then(|path| fs::canonicalize(path).ok());
+            if let Some(file) = file {
+                positions.record(file, (cursor_controller.cursor_y, cursor_controller.cursor_x));
             }
         }
+        positions.save_to(positions_file)
     }
 
-    fn refresh_screen(&mut self) -> crossterm::Result<()> {
-        queue!(self.editor_contents, cursor::MoveTo(0, 0), cursor::Hide)?;
-        self.draw_rows();
-        let cursor_x = self.cursor_controller.cursor_x;
-        let cursor_y = self.cursor_controller.cursor_y;
-        queue!(
-            self.editor_contents,
-            cursor::MoveTo(cursor_x as u16, cursor_y as u16),
-            cursor::Show
-        )?;
-        self.editor_contents.flush()
+    fn window_size(columns: u16, rows: u16) -> (usize, usize) {
+        (columns as usize, cmp::max(rows as usize, 3) - 2)
     }
-}
 
-struct CursorController {
-    cursor_x: usize,
-    cursor_y: usize
-}
+    fn resize(&mut self, columns: u16, rows: u16) {
+        self.win_size = Self::window_size(columns, rows);
+        self.editor_contents.invalidate();
+        self.layout_windows();
+        self.fit_window();
+        self.cursor_controller.scroll(&self.editor_rows);
+    }
 
-impl CursorController {
-    fn new() -> CursorController {
-        Self { cursor_x: 0, cursor_y: 0 }
+    fn layout_windows(&mut self) {
+        let (columns, rows) = self.win_size;
+        let total = match self.split {
+            Split::Horizontal => rows,
+            Split::Vertical => columns
+        };
+        let sizes = split_sizes(total, self.windows.len());
+        let mut start = 0;
+        for (window, size) in self.windows.iter_mut().zip(sizes) {
+            (window.origin, window.size) = match self.split {
+                Split::Horizontal => ((0, start), (columns, size)),
+                Split::Vertical => ((start, 0), (size, rows))
+            };
+            start += size + 1;
+        }
+    }
+
+    fn fit_window(&mut self) {
+        let (columns, rows) = self.windows[self.current_window].size;
+        self.cursor_controller.screen_rows = rows;
+        self.cursor_controller.screen_columns =
+            cmp::max(columns.saturating_sub(self.gutter_width()), 1);
+        self.cursor_controller.wrap = self.config.wrap;
+        self.cursor_controller.scroll_off = self.config.scroll_off;
+    }
+
+    fn split_window(&mut self, split: Split) {
+        if self.windows.len() > 1 && split != self.split {
+            self.set_message("Mixed horizontal and vertical splits are not supported");
+            return;
+        }
+        let total = match split {
+            Split::Horizontal => self.win_size.1,
+            Split::Vertical => self.win_size.0
+        };
+        if split_sizes(total, self.windows.len() + 1).contains(&0) {
+            self.set_message("E36: Not enough room");
+            return;
+        }
+        let mut window = Window::new(self.current_buffer, self.win_size);
+        window.cursor_controller = self.cursor_controller.clone();
+        self.windows.insert(self.current_window + 1, window);
+        self.split = split;
+        self.layout_windows();
+        self.fit_window();
+    }
+
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.set_message("E444: Cannot close last window");
+            return;
+        }
+        self.windows.remove(self.current_window);
+        let index = cmp::min(self.current_window, self.windows.len() - 1);
+        let placeholder = CursorController::new(self.win_size);
+        self.cursor_controller = mem::replace(&mut self.windows[index].cursor_controller, placeholder);
+        self.current_window = index;
+        self.swap_rows(self.windows[index].buffer);
+        self.layout_windows();
+        self.select_window(index);
+    }
+
+    fn focus_window(&mut self, index: usize) {
+        if index == self.current_window {
+            return;
+        }
+        let placeholder = CursorController::new(self.win_size);
+        let incoming = mem::replace(&mut self.windows[index].cursor_controller, placeholder);
+        self.windows[self.current_window].cursor_controller =
+            mem::replace(&mut self.cursor_controller, incoming);
+        self.current_window = index;
+        self.swap_rows(self.windows[index].buffer);
+    }
+
+    fn select_window(&mut self, index: usize) {
+        if index != self.current_window {
+            self.search_state.last_match = None;
+            self.visual_anchor = None;
+        }
+        self.focus_window(index);
+        self.set_cursor_position(self.cursor_position());
+        self.fit_window();
+    }
+
+    fn move_to_window(&mut self, direction: char) {
+        let current = self.current_window;
+        let last = self.windows.len() - 1;
+        let index = match (self.split, direction) {
+            (_, 'w') => (current + 1) % (last + 1),
+            (_, 'W') => (current + last) % (last + 1),
+            (Split::Horizontal, 'j') | (Split::Vertical, 'l') => cmp::min(current + 1, last),
+            (Split::Horizontal, 'k') | (Split::Vertical, 'h') => current.saturating_sub(1),
+            _ => current
+        };
+        self.select_window(index);
+    }
+
+    fn set_message(&mut self, message: &str) {
+        self.status_message.set_message(message.into());
     }
 
     fn move_cursor(&mut self, direction: char) {
-        match direction {
-            'j' => {
-                self.cursor_y += 1;
+        self.cursor_controller.move_cursor(direction, &self.editor_rows);
+    }
+
+    fn goto_row(&mut self, row: usize) {
+        self.cursor_controller.goto_row(row, &self.editor_rows);
+    }
+
+    fn smart_home(&mut self, past_end: bool) {
+        let cursor_y = self.cursor_controller.cursor_y;
+        let mut first_non_blank = self.editor_rows.first_non_blank(cursor_y);
+        if !past_end {
+            first_non_blank =
+                cmp::min(first_non_blank, self.editor_rows.row_len(cursor_y).saturating_sub(1));
+        }
+        match self.cursor_controller.cursor_x == first_non_blank {
+            true => self.move_cursor('0'),
+            false => self.cursor_controller.cursor_x = first_non_blank
+        }
+    }
+
+    fn cursor_position(&self) -> (usize, usize) {
+        (self.cursor_controller.cursor_y, self.cursor_controller.cursor_x)
+    }
+
+    fn set_cursor_position(&mut self, (row, at): (usize, usize)) {
+        self.cursor_controller.cursor_y =
+            cmp::min(row, self.editor_rows.number_of_rows().saturating_sub(1));
+        self.cursor_controller.cursor_x =
+            cmp::min(at, self.editor_rows.row_len(self.cursor_controller.cursor_y).saturating_sub(1));
+    }
+
+    fn scroll_wheel(&mut self, down: bool) {
+        let step = self.config.scroll_step as isize;
+        let lines = if down { step } else { -step };
+        self.cursor_controller.scroll_view(lines, &self.editor_rows);
+    }
+
+    fn click(&mut self, column: u16, row: u16, past_end: bool) {
+        let (column, row) = (column as usize, row as usize);
+        let Some(index) = self.windows.iter().position(|window| window.contains((column, row)))
+        else {
+            return;
+        };
+        self.select_window(index);
+        let (x, y) = self.windows[index].origin;
+        let (row, at) = self.cursor_controller.screen_to_buffer(
+            (column - x, row - y),
+            self.gutter_width(),
+            &self.editor_rows
+        );
+        self.cursor_controller.detached = false;
+        self.set_cursor_position((row, at));
+        if past_end {
+            self.cursor_controller.cursor_x = cmp::min(at, self.editor_rows.row_len(row));
+        }
+    }
+
+    fn jump<T>(&mut self, motion: impl FnOnce(&mut Self) -> T) -> T {
+        let before = self.cursor_position();
+        let result = motion(self);
+        if self.cursor_position() != before {
+            self.jump_list.record(before);
+        }
+        result
+    }
+
+    fn jump_back(&mut self) {
+        let current = self.cursor_position();
+        if let Some(position) = self.jump_list.back(current) {
+            self.set_cursor_position(position);
+        }
+    }
+
+    fn jump_forward(&mut self) {
+        if let Some(position) = self.jump_list.forward() {
+            self.set_cursor_position(position);
+        }
+    }
+
+    fn jump_to_matching_bracket(&mut self) {
+        let rows = 0..self.editor_rows.number_of_rows();
+        match self.editor_rows.matching_bracket(self.cursor_position(), rows) {
+            Some(position) => self.set_cursor_position(position),
+            None => self.set_message("No matching bracket")
+        }
+    }
+
+    fn goto_tag(&mut self, address: &tags::Address) {
+        let lines = (0..self.editor_rows.number_of_rows())
+            .filter_map(|row| self.editor_rows.get_row(row));
+        match address.locate(lines) {
+            Some(row) => {
+                let indentation = self.editor_rows.indentation(row).chars().count();
+                self.jump(|output| output.set_cursor_position((row, indentation)));
             }
-            'h' => {
-                self.cursor_x -= 1;
+            None => self.set_message("E434: Can't find tag pattern")
+        }
+    }
+
+    fn pop_tag(&mut self) {
+        match self.tag_stack.pop() {
+            Some((index, position)) => {
+                self.switch_buffer(index);
+                self.set_cursor_position(position);
             }
-            'k' => {
-                self.cursor_y -= 1;
+            None => self.set_message("E73: Tag stack empty")
+        }
+    }
+
+    fn set_mark(&mut self, name: char) {
+        if name.is_ascii_lowercase() {
+            let position = self.cursor_position();
+            self.editor_rows.marks.insert(name, position);
+        }
+    }
+
+    fn jump_to_mark(&mut self, name: char) {
+        match self.editor_rows.marks.get(&name) {
+            Some(&position) => self.set_cursor_position(position),
+            None => self.set_message("E20: Mark not set")
+        }
+    }
+
+    fn substitute(&mut self, range: LineRange, pattern: &str, replacement: &str, global: bool) {
+        let number_of_rows = self.editor_rows.number_of_rows();
+        let (first, last) = match range {
+            LineRange::Current => (self.cursor_controller.cursor_y, self.cursor_controller.cursor_y),
+            LineRange::Whole => (0, number_of_rows.saturating_sub(1)),
+            LineRange::Lines(first, last) if cmp::max(first, last) <= number_of_rows => {
+                let (first, last) = (cmp::min(first, last), cmp::max(first, last));
+                (first.saturating_sub(1), last.saturating_sub(1))
             }
-            'l' => {
-                self.cursor_x += 1;
+            LineRange::Lines(..) => {
+                self.set_message("E16: Invalid range");
+                return;
             }
-            _ => unimplemented!(),
+        };
+        if !pattern.is_empty() {
+            self.search_state.query = pattern.into();
+        }
+        let query = self.search_state.query.clone();
+        if query.is_empty() {
+            self.set_message("E35: No previous regular expression");
+            return;
+        }
+        let Some(pattern) = self.search_pattern(&query) else {
+            return;
+        };
+        let mut substitutions = 0;
+        let mut lines = 0;
+        for row in first..=last {
+            let count = self.editor_rows.substitute(row, &pattern, replacement, global);
+            if count > 0 {
+                substitutions += count;
+                lines += 1;
+                self.set_cursor_position((row, 0));
+            }
+        }
+        match substitutions {
+            0 => self.set_message(&format!("E486: Pattern not found: {}", query)),
+            1 => {}
+            _ => self.set_message(&format!(
+                "{} substitution{} on {} line{}",
+                substitutions,
+                if substitutions == 1 { "" } else { "s" },
+                lines,
+                if lines == 1 { "" } else { "s" }
+            ))
         }
     }
 
-}
+    fn undo(&mut self) {
+        if self.editor_rows.read_only {
+            self.set_message(READ_ONLY_MESSAGE);
+            return;
+        }
+        match self.editor_rows.undo() {
+            Some(cursor) => self.set_cursor_position(cursor),
+            None => self.set_message("Already at oldest change")
+        }
+    }
 
-struct Reader;
+    fn redo(&mut self) {
+        if self.editor_rows.read_only {
+            self.set_message(READ_ONLY_MESSAGE);
+            return;
+        }
+        match self.editor_rows.redo() {
+            Some(cursor) => self.set_cursor_position(cursor),
+            None => self.set_message("Already at newest change")
+        }
+    }
 
-impl Reader {
-    fn read_key(&self) -> crossterm::Result<KeyEvent> {
+    fn move_to_line_end(&mut self, past_end: bool) {
+        self.move_cursor('$');
+        if past_end {
+            self.cursor_controller.cursor_x =
+                self.editor_rows.row_len(self.cursor_controller.cursor_y);
+        }
+    }
+
+    fn page(&mut self, direction: KeyCode) {
+        self.cursor_controller.page(direction, &self.editor_rows);
+    }
+
+    fn half_page(&mut self, down: bool) {
+        self.cursor_controller.half_page(down, &self.editor_rows);
+    }
+
+    fn prompt(
+        &mut self,
+        reader: &Reader,
+        prompt: &str,
+        mut callback: Option<&mut PromptCallback>
+    ) -> crossterm::Result<Option<String>> {
+        let mut input = String::new();
         loop {
-            if event::poll(Duration::from_millis(5000))? {
-                if let Event::Key(event) = event::read()? {
-                    return Ok(event);
+            self.set_message(&format!("{}{}{}", prompt, input, self.prompt_hint));
+            self.refresh_screen(Mode::Normal)?;
+            let key = match reader.read_event()? {
+                Some(Event::Key(key)) => key,
+                Some(Event::Resize(columns, rows)) => {
+                    self.resize(columns, rows);
+                    continue;
+                }
+                _ => continue
+            };
+            let result = match key {
+                KeyEvent {
+                    code: KeyCode::Esc,
+                    ..
+                } => Some(None),
+                KeyEvent {
+                    code: KeyCode::Enter,
+                    ..
+                } => Some(Some(input.clone())),
+                KeyEvent {
+                    code: KeyCode::Backspace,
+                    ..
+                } => {
+                    input.pop();
+                    None
                 }
+                KeyEvent {
+                    code: KeyCode::Char(ch),
+                    modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+                    ..
+                } => {
+                    input.push(ch);
+                    None
+                }
+                _ => None
+            };
+            if result.is_some() {
+                self.set_message("");
+            }
+            if let Some(callback) = callback.as_mut() {
+                callback(self, &input, key);
+            }
+            if let Some(result) = result {
+                return Ok(result);
             }
         }
     }
-}
 
-struct EditorContents {
-    content: String
-}
+    fn find(&mut self, reader: &Reader) -> crossterm::Result<()> {
+        let saved_cursor = (
+            self.cursor_controller.cursor_x,
+            self.cursor_controller.cursor_y,
+            self.cursor_controller.row_offset,
+            self.cursor_controller.column_offset
+        );
+        let restore_cursor = |output: &mut Output| {
+            (
+                output.cursor_controller.cursor_x,
+                output.cursor_controller.cursor_y,
+                output.cursor_controller.row_offset,
+                output.cursor_controller.column_offset
+            ) = saved_cursor;
+        };
+        let mut direction = SearchDirection::Forward;
+        let mut callback = |output: &mut Output, query: &str, key: KeyEvent| {
+            let mut search_from = (saved_cursor.1, saved_cursor.0);
+            match key.code {
+                KeyCode::Esc => {
+                    restore_cursor(output);
+                    output.search_state.highlighted_match = None;
+                    return;
+                }
+                KeyCode::Enter => {
+                    output.search_state.highlighted_match = None;
+                    if !query.is_empty() && output.search_state.last_match.is_none() {
+                        match Pattern::new(query, output.config.regex) {
+                            Ok(_) => output.set_message(&format!("E486: Pattern not found: {}", query)),
+                            Err(err) => output.set_message(&err)
+                        }
+                    }
+                    output.search_state.query = query.into();
+                    output.search_state.direction = direction;
+                    return;
+                }
+                KeyCode::Down | KeyCode::Right => {
+                    direction = SearchDirection::Forward;
+                    search_from = (
+                        output.cursor_controller.cursor_y,
+                        output.cursor_controller.cursor_x
+                    );
+                }
+                KeyCode::Up | KeyCode::Left => {
+                    direction = SearchDirection::Backward;
+                    search_from = (
+                        output.cursor_controller.cursor_y,
+                        output.cursor_controller.cursor_x
+                    );
+                }
+                KeyCode::Char(_) | KeyCode::Backspace => {
+                    direction = SearchDirection::Forward;
+                    restore_cursor(output);
+                }
+                _ => return
+            }
+            output.search_state.last_match = None;
+            output.search_state.highlighted_match = None;
+            let Ok(pattern) = Pattern::new(query, output.config.regex) else {
+                return;
+            };
+            if query.is_empty() {
+                return;
+            }
+            if let Some((position, len)) = output.editor_rows.find(&pattern, search_from, direction) {
+                (output.cursor_controller.cursor_y, output.cursor_controller.cursor_x) = position;
+                output.search_state.last_match = Some(position);
+                output.search_state.highlighted_match = Some((position, len));
+            }
+        };
+        self.prompt(reader, "/", Some(&mut callback))?;
+        Ok(())
+    }
 
-impl EditorContents {
-    fn new() -> Self {
-        Self { content: String::new() }
+    fn find_next(&mut self, reverse: bool) {
+        if self.search_state.query.is_empty() {
+            self.set_message("E35: No previous regular expression");
+            return;
+        }
+        let direction = match reverse {
+            true => self.search_state.direction.reverse(),
+            false => self.search_state.direction
+        };
+        let cursor = (
+            self.cursor_controller.cursor_y,
+            self.cursor_controller.cursor_x
+        );
+        let query = self.search_state.query.clone();
+        let Some(pattern) = self.search_pattern(&query) else {
+            return;
+        };
+        match self.editor_rows.find(&pattern, cursor, direction) {
+            Some((position, _)) => {
+                let wrapped = match direction {
+                    SearchDirection::Forward => position <= cursor,
+                    SearchDirection::Backward => position >= cursor
+                };
+                if wrapped {
+                    self.set_message(match direction {
+                        SearchDirection::Forward => "search hit BOTTOM, continuing at TOP",
+                        SearchDirection::Backward => "search hit TOP, continuing at BOTTOM"
+                    });
+                } else {
+                    let prefix = match self.search_state.direction {
+                        SearchDirection::Forward => '/',
+                        SearchDirection::Backward => '?'
+                    };
+                    self.set_message(&format!("{}{}", prefix, query));
+                }
+                (self.cursor_controller.cursor_y, self.cursor_controller.cursor_x) = position;
+                self.search_state.last_match = Some(position);
+            }
+            None => self.set_message(&format!("E486: Pattern not found: {}", query))
+        }
     }
 
-    fn push(&mut self, ch: char) {
-        self.content.push(ch);
+    fn search_pattern(&mut self, query: &str) -> Option<Pattern> {
+        match Pattern::new(query, self.config.regex) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                self.set_message(&err);
+                None
+            }
+        }
     }
 
-    fn push_str(&mut self, string: &str) {
-        self.content.push_str(string);
+    fn insert_char(&mut self, ch: char) {
+        self.editor_rows.insert_char(
+            self.cursor_controller.cursor_y,
+            self.cursor_controller.cursor_x,
+            ch
+        );
+        self.cursor_controller.cursor_x += 1;
     }
-}
 
-impl io::Write for EditorContents {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match std::str::from_utf8(buf) {
-            Ok(s) => {
-                self.content.push_str(s);
-                Ok(s.len())
+    fn type_char(&mut self, ch: char) {
+        if !self.config.autopairs {
+            return self.insert_char(ch);
+        }
+        let (row, at) = self.cursor_position();
+        let line = self.editor_rows.get_row(row).unwrap_or_default();
+        let previous = at.checked_sub(1).and_then(|at| line.chars().nth(at));
+        let next = line.chars().nth(at);
+        let after_word = previous.is_some_and(|ch| CharClass::of(ch) == CharClass::Word);
+        match closing_pair(ch) {
+            _ if next == Some(ch) && ")]}\"'".contains(ch) => self.cursor_controller.cursor_x += 1,
+            Some(close) if close != ch || !after_word => {
+                self.insert_char(ch);
+                self.insert_char(close);
+                self.cursor_controller.cursor_x -= 1;
             }
-            Err(_) => Err(io::ErrorKind::WriteZero.into())
+            _ => self.insert_char(ch)
         }
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        let out = write!(stdout(), "{}", self.content);
-        stdout().flush()?;
-        self.content.clear();
-        out
+    fn insert_tab(&mut self) {
+        if !self.config.expand_tab {
+            return self.insert_char('\t');
+        }
+        let cursor_y = self.cursor_controller.cursor_y;
+        let render_x = self.editor_rows.cx_to_rx(cursor_y, self.cursor_controller.cursor_x);
+        let tab_width = self.config.tab_width;
+        let spaces = " ".repeat(tab_width - render_x % tab_width);
+        let cursor_x = self.cursor_controller.cursor_x;
+        (_, self.cursor_controller.cursor_x) =
+            self.editor_rows.insert_text(cursor_y, cursor_x, &spaces);
     }
-}
 
-struct Editor {
-    reader: Reader,
-    output: Output
-}
+    fn save(&mut self, reader: &Reader) -> crossterm::Result<bool> {
+        if self.editor_rows.read_only {
+            self.set_message(READ_ONLY_MESSAGE);
+            return Ok(false);
+        }
+        if self.editor_rows.filename.is_none() {
+            match self.prompt(reader, "Save as: ", None)? {
+                Some(filename) if !filename.is_empty() => {
+                    self.editor_rows.set_filename(filename.into());
+                }
+                _ => {
+                    self.set_message("Save aborted");
+                    return Ok(false);
+                }
+            }
+        }
+        if self.editor_rows.disk_change() == DiskChange::Modified {
+            let answer = self.prompt(
+                reader,
+                "WARNING: The file has been changed since reading it!!! Write anyway (y/n)? ",
+                None
+            )?;
+            if answer.as_deref() != Some("y") {
+                self.set_message("Save aborted");
+                return Ok(false);
+            }
+        }
+        match self.editor_rows.save(&self.config) {
+            Ok(len) => {
+                self.set_message(&format!("{} bytes written to disk", len));
+                Ok(true)
+            }
+            Err(err) => {
+                self.set_message(&format!("Can't save! I/O error: {}", err));
+                Ok(false)
+            }
+        }
+    }
 
-impl Editor {
-    fn new() -> Self {
-        Self {
-            reader: Reader,
-            output: Output::new()
+    fn autosave(&mut self) {
+        if self.editor_rows.disk_change() == DiskChange::Modified {
+            self.check_disk_change();
+            return;
         }
+        match self.editor_rows.save(&self.config) {
+            Ok(_) => self.set_message("auto-saved"),
+            Err(err) => self.set_message(&format!("Can't save! I/O error: {}", err))
+        }
+        self.needs_redraw = true;
     }
 
-    fn process_keypress(&mut self) -> crossterm::Result<bool> {
-        match self.reader.read_key()? {
-            KeyEvent {
-                code: KeyCode::Char('q'),
-                modifiers: event::KeyModifiers::CONTROL,
-                ..
-            } => return Ok(false),
-            KeyEvent {
-                code: KeyCode::Char(val),
-                modifiers: event::KeyModifiers::NONE,
-                ..
-            }  => {
-                match val {
-                    'h'| 'j'|'k'|'l' => self.output.move_cursor(val),
-                    _=> {}
-                }
-                
-            },
-            _ => {}
+    fn check_disk_change(&mut self) {
+        let name = self.editor_rows.display_name();
+        match self.editor_rows.disk_change() {
+            DiskChange::Modified => self.set_message(&format!(
+                "W11: Warning: File \"{}\" has changed since editing started",
+                name
+            )),
+            DiskChange::Deleted => {
+                self.set_message(&format!("E211: File \"{}\" no longer available", name))
+            }
+            DiskChange::Unchanged => return
         }
-        Ok(true)
+        self.needs_redraw = true;
     }
 
-    fn run(&mut self) -> crossterm::Result<bool> {
-        self.output.refresh_screen()?;
-        self.process_keypress()
+    fn reload(&mut self) {
+        let Some(path) = self.editor_rows.filename.clone() else {
+            return self.set_message("E32: No file name");
+        };
+        let _ = self.editor_rows.remove_swap();
+        match EditorRows::from_file(&path) {
+            Ok(mut editor_rows) => {
+                editor_rows.read_only = self.editor_rows.read_only;
+                let position = self.cursor_position();
+                self.open(editor_rows);
+                self.set_cursor_position(position);
+                self.set_message(&format!(
+                    "\"{}\" {}L",
+                    path.display(),
+                    self.editor_rows.number_of_rows()
+                ));
+                self.offer_recovery();
+            }
+            Err(err) => self.set_message(&format!("Can't open {}: {}", path.display(), err))
+        }
     }
-}
 
-fn main() -> crossterm::Result<()> {
-    let _clean_up = CleanUp;
-    terminal::enable_raw_mode()?;
+    fn open(&mut self, editor_rows: EditorRows) {
+        self.editor_rows = editor_rows;
+        self.editor_rows.set_tab_stop(self.config.tab_width);
+        self.cursor_controller = CursorController::new(self.win_size);
+        self.fit_window();
+        self.search_state.last_match = None;
+    }
 
-    let mut editor = Editor::new();
-    while editor.run()? {}
-    Ok(())
+    fn add_buffer(&mut self, mut editor_rows: EditorRows) {
+        editor_rows.set_tab_stop(self.config.tab_width);
+        self.buffers.push(Buffer::new(editor_rows, self.win_size));
+        self.switch_buffer(self.buffers.len() - 1);
+        self.restore_position();
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.current_buffer || index >= self.buffers.len() {
+            return;
+        }
+        let placeholder = CursorController::new(self.win_size);
+        let incoming = mem::replace(&mut self.buffers[index].cursor_controller, placeholder);
+        self.buffers[self.current_buffer].cursor_controller =
+            mem::replace(&mut self.cursor_controller, incoming);
+        self.alternate_buffer = Some(self.current_buffer);
+        self.swap_rows(index);
+        self.windows[self.current_window].buffer = index;
+        self.fit_window();
+        self.search_state.last_match = None;
+        self.visual_anchor = None;
+    }
+
+    fn switch_to_alternate(&mut self) {
+        match self.alternate_buffer {
+            Some(index) => self.switch_buffer(index),
+            None => self.set_message("E23: No alternate file")
+        }
+    }
+
+    fn swap_rows(&mut self, index: usize) {
+        if index == self.current_buffer {
+            return;
+        }
+        let incoming = mem::replace(&mut self.buffers[index].editor_rows, EditorRows::new());
+        self.buffers[self.current_buffer].editor_rows = mem::replace(&mut self.editor_rows, incoming);
+        self.current_buffer = index;
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) {
+        let len = self.buffers.len();
+        let index = match forward {
+            true => (self.current_buffer + 1) % len,
+            false => (self.current_buffer + len - 1) % len
+        };
+        self.switch_buffer(index);
+    }
+
+    fn buffer_rows(&self, index: usize) -> &EditorRows {
+        match index == self.current_buffer {
+            true => &self.editor_rows,
+            false => &self.buffers[index].editor_rows
+        }
+    }
+
+    fn buffer_rows_mut(&mut self, index: usize) -> &mut EditorRows {
+        match index == self.current_buffer {
+            true => &mut self.editor_rows,
+            false => &mut self.buffers[index].editor_rows
+        }
+    }
+
+    fn show_help(&mut self) {
+        let mut help = EditorRows::new();
+        help.row_contents = ["Key bindings (q to close)".into(), String::new()]
+            .into_iter()
+            .chain(self.config.help())
+            .map(|line| Row::new(line, self.config.tab_width))
+            .collect();
+        help.scratch_name = Some(HELP_NAME);
+        help.read_only = true;
+        let existing = (0..self.buffers.len())
+            .find(|&index| self.buffer_rows(index).scratch_name == Some(HELP_NAME));
+        match existing {
+            Some(index) => {
+                *self.buffer_rows_mut(index) = help;
+                self.switch_buffer(index);
+                self.set_cursor_position((0, 0));
+            }
+            None => self.add_buffer(help)
+        }
+    }
+
+    fn find_buffer(&self, path: &Path) -> Option<usize> {
+        (0..self.buffers.len())
+            .find(|&index| self.buffer_rows(index).filename.as_deref() == Some(path))
+    }
+
+    fn modified_buffer(&self) -> Option<usize> {
+        (0..self.buffers.len()).find(|&index| self.buffer_rows(index).dirty > 0)
+    }
+
+    fn buffer_name(&self, index: usize) -> String {
+        self.buffer_rows(index).display_name()
+    }
+
+    fn list_buffers(&mut self) {
+        let listing = (0..self.buffers.len())
+            .map(|index| {
+                format!(
+                    "{}{}{} \"{}\"",
+                    index + 1,
+                    if index == self.current_buffer {
+                        "%"
+                    } else if Some(index) == self.alternate_buffer {
+                        "#"
+                    } else {
+                        ""
+                    },
+                    if self.buffer_rows(index).dirty > 0 { "+" } else { "" },
+                    self.buffer_name(index)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" | ");
+        self.set_message(&listing);
+    }
+
+    fn file_info(&self) -> String {
+        let rows = self.editor_rows.number_of_rows();
+        let modified = if self.editor_rows.dirty > 0 { " [Modified]" } else { "" };
+        let name = self.editor_rows.display_name();
+        if rows == 0 {
+            return format!("\"{}\"{} --No lines in buffer--", name, modified);
+        }
+        let (row, at) = self.cursor_position();
+        format!(
+            "\"{}\"{} {} lines, {} characters --line {} of {} --{}%-- col {}",
+            name,
+            modified,
+            rows,
+            self.editor_rows.char_count(),
+            row + 1,
+            rows,
+            (row + 1) * 100 / rows,
+            at + 1
+        )
+    }
+
+    fn show_file_info(&mut self) {
+        let info = self.file_info();
+        self.set_message(&info);
+    }
+
+    fn replace_chars(&mut self, ch: char, count: usize) {
+        let (cursor_y, cursor_x) = self.cursor_position();
+        if count == 0 || cursor_x + count > self.editor_rows.row_len(cursor_y) {
+            return;
+        }
+        self.editor_rows
+            .delete_range((cursor_y, cursor_x), (cursor_y, cursor_x + count));
+        self.editor_rows
+            .insert_text(cursor_y, cursor_x, &ch.to_string().repeat(count));
+        self.cursor_controller.cursor_x = cursor_x + count - 1;
+    }
+
+    fn join_lines(&mut self, count: usize) {
+        let cursor_y = self.cursor_controller.cursor_y;
+        for _ in 1..cmp::max(count, 2) {
+            if cursor_y + 1 >= self.editor_rows.number_of_rows() {
+                break;
+            }
+            let indent = self.editor_rows.indentation(cursor_y + 1).len();
+            self.editor_rows
+                .delete_range((cursor_y + 1, 0), (cursor_y + 1, indent));
+            let current = self.editor_rows.get_row(cursor_y).unwrap_or_default();
+            let next = self.editor_rows.get_row(cursor_y + 1).unwrap_or_default();
+            let needs_space = !current.is_empty()
+                && !current.ends_with([' ', '\t'])
+                && !next.is_empty()
+                && !next.starts_with(')');
+            let row_len = self.editor_rows.row_len(cursor_y);
+            self.editor_rows.join_adjacent_rows(cursor_y);
+            if needs_space {
+                self.editor_rows.insert_char(cursor_y, row_len, ' ');
+            }
+            self.cursor_controller.cursor_x =
+                cmp::min(row_len, self.editor_rows.row_len(cursor_y).saturating_sub(1));
+        }
+    }
+
+    fn delete_chars(&mut self, count: usize) {
+        let cursor_x = self.cursor_controller.cursor_x;
+        let cursor_y = self.cursor_controller.cursor_y;
+        let row_len = self.editor_rows.row_len(cursor_y);
+        for _ in cursor_x..cmp::min(cursor_x + count, row_len) {
+            self.editor_rows.delete_char(cursor_y, cursor_x);
+        }
+        self.cursor_controller.cursor_x =
+            cmp::min(cursor_x, self.editor_rows.row_len(cursor_y).saturating_sub(1));
+    }
+
+    fn yank_lines(&self, count: usize) -> Register {
+        let cursor_y = self.cursor_controller.cursor_y;
+        let end = cmp::min(cursor_y + count, self.editor_rows.number_of_rows());
+        Register {
+            contents: (cursor_y..end)
+                .filter_map(|row| self.editor_rows.get_row(row))
+                .map(String::from)
+                .collect(),
+            linewise: true
+        }
+    }
+
+    fn paste(&mut self, register: &Register, count: usize, after: bool) {
+        if register.contents.is_empty() {
+            return;
+        }
+        let cursor_y = self.cursor_controller.cursor_y;
+        if register.linewise {
+            let first_row = cursor_y + after as usize;
+            let mut row = first_row;
+            for _ in 0..count {
+                for line in &register.contents {
+                    self.editor_rows.insert_row(row, line.clone());
+                    row += 1;
+                }
+            }
+            self.cursor_controller.cursor_y = first_row;
+            self.cursor_controller.cursor_x = 0;
+        } else {
+            let cursor_x = self.cursor_controller.cursor_x;
+            let row_len = self.editor_rows.row_len(cursor_y);
+            let at = match after && row_len > 0 {
+                true => cmp::min(cursor_x + 1, row_len),
+                false => cursor_x
+            };
+            let text = register.contents.join("\n").repeat(count);
+            let end = self.editor_rows.insert_text(cursor_y, at, &text);
+            if register.contents.len() > 1 {
+                (self.cursor_controller.cursor_y, self.cursor_controller.cursor_x) = (cursor_y, at);
+            } else {
+                (self.cursor_controller.cursor_y, self.cursor_controller.cursor_x) =
+                    (end.0, end.1.saturating_sub(1));
+            }
+        }
+    }
+
+    fn yank_selection(&mut self, mode: Mode) -> Option<Register> {
+        if mode == Mode::VisualBlock {
+            return self.yank_block();
+        }
+        let (start, end) = self.selection(mode)?;
+        if mode == Mode::VisualLine {
+            self.set_cursor_position(start);
+            return Some(self.yank_lines(end.0 - start.0 + 1));
+        }
+        let end = self.editor_rows.range_end(end);
+        let text = self.editor_rows.text_range(start, end);
+        self.set_cursor_position(start);
+        Some(Register {
+            contents: text.split('\n').map(String::from).collect(),
+            linewise: false
+        })
+    }
+
+    fn delete_selection(&mut self, mode: Mode) -> Option<Register> {
+        if mode == Mode::VisualBlock {
+            return self.delete_block();
+        }
+        let (start, end) = self.selection(mode)?;
+        if mode == Mode::VisualLine {
+            self.set_cursor_position(start);
+            let register = self.yank_lines(end.0 - start.0 + 1);
+            (start.0..=end.0).for_each(|_| self.delete_row());
+            return Some(register);
+        }
+        let end = self.editor_rows.range_end(end);
+        let register = self.delete_text(start, end);
+        self.set_cursor_position(start);
+        register
+    }
+
+    fn change_case(&mut self, start: (usize, usize), end: (usize, usize), case: Case) {
+        let text = self.editor_rows.text_range(start, end);
+        let converted = case.apply(&text);
+        if converted != text {
+            self.editor_rows.delete_range(start, end);
+            self.editor_rows.insert_text(start.0, start.1, &converted);
+        }
+    }
+
+    fn change_lines_case(&mut self, rows: Range<usize>, case: Case) {
+        let last = cmp::min(rows.end, self.editor_rows.number_of_rows()).saturating_sub(1);
+        let end = (last, self.editor_rows.row_len(last));
+        self.change_case((rows.start, 0), end, case);
+        self.set_cursor_position((rows.start, self.cursor_controller.cursor_x));
+    }
+
+    fn change_selection_case(&mut self, mode: Mode, case: Case) {
+        if mode == Mode::VisualBlock {
+            let Some((rows, columns)) = self.block() else {
+                return;
+            };
+            for row in rows.clone() {
+                let (from, to) = self.block_text(row, &columns);
+                self.change_case((row, from), (row, to), case);
+            }
+            self.set_cursor_position((rows.start, self.block_text(rows.start, &columns).0));
+            return;
+        }
+        let Some((start, end)) = self.selection(mode) else {
+            return;
+        };
+        let end = match mode {
+            Mode::VisualLine => end,
+            _ => self.editor_rows.range_end(end)
+        };
+        self.change_case(start, end, case);
+        self.set_cursor_position(start);
+    }
+
+    fn toggle_case(&mut self, count: usize) {
+        let (row, at) = self.cursor_position();
+        let end = cmp::min(at + count, self.editor_rows.row_len(row));
+        self.change_case((row, at), (row, end), Case::Toggle);
+        self.set_cursor_position((row, end));
+    }
+
+    fn delete_text(&mut self, start: (usize, usize), end: (usize, usize)) -> Option<Register> {
+        let text = self.editor_rows.delete_range(start, end);
+        (self.cursor_controller.cursor_y, self.cursor_controller.cursor_x) = start;
+        (!text.is_empty()).then(|| Register {
+            contents: text.split('\n').map(String::from).collect(),
+            linewise: false
+        })
+    }
+
+    fn change_word(&mut self, count: usize) -> Option<Register> {
+        let (cursor_y, cursor_x) = self.cursor_position();
+        let mut end = self.editor_rows.run_end((cursor_y, cursor_x));
+        if self.editor_rows.char_class_at((cursor_y, cursor_x)) != CharClass::Whitespace {
+            for _ in 1..count {
+                if self.editor_rows.char_class_at((cursor_y, end)) == CharClass::Whitespace {
+                    end = self.editor_rows.run_end((cursor_y, end));
+                }
+                end = self.editor_rows.run_end((cursor_y, end));
+            }
+        }
+        self.delete_text((cursor_y, cursor_x), (cursor_y, end))
+    }
+
+    fn yank_text(&self, start: (usize, usize), end: (usize, usize)) -> Option<Register> {
+        let text = self.editor_rows.text_range(start, end);
+        (!text.is_empty()).then(|| Register {
+            contents: text.split('\n').map(String::from).collect(),
+            linewise: false
+        })
+    }
+
+    fn motion_range(&self, motion: char, count: usize) -> Option<((usize, usize), (usize, usize))> {
+        let start = self.cursor_position();
+        let last_row = self.editor_rows.number_of_rows().checked_sub(1)?;
+        match motion {
+            'w' => {
+                let mut end = start;
+                for _ in 0..count {
+                    match self.editor_rows.next_word_start(end) {
+                        Some(next) => end = next,
+                        None => {
+                            end = (last_row, self.editor_rows.row_len(last_row));
+                            break;
+                        }
+                    }
+                }
+                if end.0 > start.0 {
+                    end = (end.0 - 1, self.editor_rows.row_len(end.0 - 1));
+                }
+                Some((start, end))
+            }
+            '$' => {
+                let row = cmp::min(start.0 + count - 1, last_row);
+                Some((start, (row, self.editor_rows.row_len(row))))
+            }
+            '0' => Some(((start.0, 0), start)),
+            '{' | '}' | '(' | ')' => {
+                let mut end = start;
+                for _ in 0..count {
+                    end = match motion {
+                        '{' => self.editor_rows.previous_paragraph(end.0),
+                        '}' => self.editor_rows.next_paragraph(end.0),
+                        '(' => self.editor_rows.previous_sentence(end),
+                        _ => self.editor_rows.next_sentence(end)
+                    };
+                }
+                let buffer_end = (last_row, self.editor_rows.row_len(last_row).saturating_sub(1));
+                if matches!(motion, '}' | ')') && end == buffer_end {
+                    end.1 = self.editor_rows.row_len(last_row);
+                }
+                Some((cmp::min(start, end), cmp::max(start, end)))
+            }
+            '^' => {
+                let first_non_blank = self.editor_rows.first_non_blank(start.0);
+                Some((
+                    cmp::min(start, (start.0, first_non_blank)),
+                    cmp::max(start, (start.0, first_non_blank))
+                ))
+            }
+            _ => None
+        }
+    }
+
+    fn toggle_fold(&mut self) {
+        if !self.editor_rows.toggle_fold(self.cursor_controller.cursor_y) {
+            self.set_message("E490: No fold found");
+        }
+    }
+
+    fn toggle_comment(&mut self, rows: Range<usize>) {
+        let syntax = self.editor_rows.syntax;
+        let Some(token) = syntax.comment() else {
+            return self.set_message(&format!("No comment syntax for {}", syntax.name()));
+        };
+        let rows = rows.start..cmp::min(rows.end, self.editor_rows.number_of_rows());
+        let lines: Vec<(usize, usize)> = rows
+            .filter_map(|row| {
+                let line = self.editor_rows.get_row(row)?;
+                let indent = line.chars().take_while(|ch| ch.is_whitespace()).count();
+                (indent < line.chars().count()).then_some((row, indent))
+            })
+            .collect();
+        let commented = |editor_rows: &EditorRows, (row, indent): (usize, usize)| {
+            editor_rows
+                .slice(row, indent, editor_rows.row_len(row))
+                .starts_with(token)
+        };
+        let uncomment = lines.iter().all(|&line| commented(&self.editor_rows, line));
+        let comment = format!("{} ", token);
+        for (row, indent) in lines {
+            match uncomment {
+                true => {
+                    let rest = self.editor_rows.slice(row, indent, self.editor_rows.row_len(row));
+                    let len = match rest.starts_with(&comment) {
+                        true => comment.chars().count(),
+                        false => token.chars().count()
+                    };
+                    self.editor_rows.delete_range((row, indent), (row, indent + len));
+                }
+                false => {
+                    self.editor_rows.insert_text(row, indent, &comment);
+                }
+            }
+        }
+        self.set_cursor_position(self.cursor_position());
+    }
+
+    fn shift_lines(&mut self, rows: Range<usize>, indent: bool) {
+        let width = match self.config.shift_width {
+            0 => self.config.tab_width,
+            width => width
+        };
+        let unit = match self.config.expand_tab {
+            true => " ".repeat(width),
+            false => "\t".into()
+        };
+        let first_row = rows.start;
+        for row in rows.start..cmp::min(rows.end, self.editor_rows.number_of_rows()) {
+            let line = self.editor_rows.get_row(row).unwrap_or_default();
+            if indent {
+                if !line.is_empty() {
+                    self.editor_rows.insert_text(row, 0, &unit);
+                }
+                continue;
+            }
+            let len = match line.starts_with('\t') {
+                true => 1,
+                false => line.chars().take(width).take_while(|&ch| ch == ' ').count()
+            };
+            self.editor_rows.delete_range((row, 0), (row, len));
+        }
+        let indentation = self.editor_rows.indentation(first_row).chars().count();
+        self.set_cursor_position((first_row, indentation));
+    }
+
+    fn open_line(&mut self, below: bool) {
+        let row = self.cursor_controller.cursor_y + below as usize;
+        self.editor_rows.insert_row(row, String::new());
+        self.cursor_controller.cursor_y = row;
+        self.cursor_controller.cursor_x = 0;
+    }
+
+    fn delete_row(&mut self) {
+        let cursor_y = self.cursor_controller.cursor_y;
+        self.editor_rows.delete_row(cursor_y);
+        self.cursor_controller.cursor_y =
+            cmp::min(cursor_y, self.editor_rows.number_of_rows().saturating_sub(1));
+        self.cursor_controller.cursor_x = 0;
+    }
+
+    fn insert_newline(&mut self) {
+        let cursor_y = self.cursor_controller.cursor_y;
+        let cursor_x = self.cursor_controller.cursor_x;
+        let mut indent = match self.config.auto_indent {
+            true => self.editor_rows.indentation(cursor_y).to_string(),
+            false => String::new()
+        };
+        indent.truncate(cursor_x);
+        self.editor_rows.insert_newline(cursor_y, cursor_x);
+        self.cursor_controller.cursor_x = 0;
+        self.cursor_controller.cursor_y += 1;
+        if !indent.is_empty() {
+            let carried = self.editor_rows.indentation(cursor_y + 1).len();
+            self.editor_rows.delete_range((cursor_y + 1, 0), (cursor_y + 1, carried));
+            (_, self.cursor_controller.cursor_x) =
+                self.editor_rows.insert_text(cursor_y + 1, 0, &indent);
+        }
+    }
+
+    fn delete_char(&mut self) {
+        let cursor_x = self.cursor_controller.cursor_x;
+        let cursor_y = self.cursor_controller.cursor_y;
+        if self.config.autopairs && cursor_x > 0 {
+            let line = self.editor_rows.get_row(cursor_y).unwrap_or_default();
+            let mut chars = line.chars().skip(cursor_x - 1);
+            if let (Some(open), Some(close)) = (chars.next(), chars.next()) {
+                if closing_pair(open) == Some(close) {
+                    self.editor_rows.delete_char(cursor_y, cursor_x);
+                }
+            }
+        }
+        if cursor_x > 0 {
+            self.editor_rows.delete_char(cursor_y, cursor_x - 1);
+            self.cursor_controller.cursor_x -= 1;
+        } else if cursor_y > 0 && cursor_y < self.editor_rows.number_of_rows() {
+            let previous_len = self.editor_rows.row_len(cursor_y - 1);
+            self.editor_rows.join_adjacent_rows(cursor_y - 1);
+            self.cursor_controller.cursor_x = previous_len;
+            self.cursor_controller.cursor_y -= 1;
+        }
+    }
+
+    fn delete_char_forward(&mut self) {
+        let cursor_x = self.cursor_controller.cursor_x;
+        let cursor_y = self.cursor_controller.cursor_y;
+        let row_len = self.editor_rows.row_len(cursor_y);
+        if cursor_x < row_len {
+            self.editor_rows.delete_char(cursor_y, cursor_x);
+        } else if cursor_y + 1 < self.editor_rows.number_of_rows() {
+            self.editor_rows.join_adjacent_rows(cursor_y);
+        }
+    }
+
+    fn gutter_width(&self) -> usize {
+        if self.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        cmp::max(self.editor_rows.number_of_rows(), 1).ilog10() as usize + 2
+    }
+
+    fn line_number(&self, file_row: usize) -> usize {
+        let cursor_y = self.cursor_controller.cursor_y;
+        let distance = file_row.abs_diff(cursor_y);
+        match self.line_numbers {
+            LineNumbers::Off | LineNumbers::Absolute => file_row + 1,
+            LineNumbers::Relative => distance,
+            LineNumbers::Hybrid if file_row == cursor_y => file_row + 1,
+            LineNumbers::Hybrid => distance
+        }
+    }
+
+    fn set_option(&mut self, option: &str) {
+        let numbers = &mut self.line_numbers;
+        match option {
+            "number" | "nu" => *numbers = numbers.with_number(true),
+            "nonumber" | "nonu" => *numbers = numbers.with_number(false),
+            "number!" | "nu!" | "invnumber" | "invnu" => {
+                *numbers = numbers.with_number(!numbers.number())
+            }
+            "relativenumber" | "rnu" => *numbers = numbers.with_relative(true),
+            "norelativenumber" | "nornu" => *numbers = numbers.with_relative(false),
+            "relativenumber!" | "rnu!" | "invrelativenumber" | "invrnu" => {
+                *numbers = numbers.with_relative(!numbers.relative())
+            }
+            "autoindent" | "ai" => self.config.auto_indent = true,
+            "noautoindent" | "noai" => self.config.auto_indent = false,
+            "expandtab" | "et" => self.config.expand_tab = true,
+            "noexpandtab" | "noet" => self.config.expand_tab = false,
+            "autopairs" | "ap" => self.config.autopairs = true,
+            "noautopairs" | "noap" => self.config.autopairs = false,
+            _ if option.starts_with("colorcolumn=") || option.starts_with("cc=") => {
+                let value = option.split_once('=').map_or("", |(_, value)| value);
+                match config::parse_columns(value) {
+                    Some(columns) => self.config.color_columns = columns,
+                    None => self.set_message(&format!("E474: Invalid argument: {}", option))
+                }
+            }
+            _ if option.starts_with("scrolloff=") || option.starts_with("so=") => {
+                let value = option.split_once('=').map_or("", |(_, value)| value);
+                match value.parse() {
+                    Ok(scroll_off) => self.config.scroll_off = scroll_off,
+                    Err(_) => {
+                        self.set_message(&format!("E521: Number required after =: {}", option))
+                    }
+                }
+            }
+            "cursorline" | "cul" => self.config.cursor_line = true,
+            "nocursorline" | "nocul" => self.config.cursor_line = false,
+            "ruler" | "ru" => self.config.ruler = true,
+            "noruler" | "noru" => self.config.ruler = false,
+            "wrap" => self.config.wrap = true,
+            "nowrap" => self.config.wrap = false,
+            _ if option.starts_with("fileformat=") || option.starts_with("ff=") => {
+                let value = option.split_once('=').map_or("", |(_, value)| value);
+                match FileFormat::parse(value) {
+                    Some(_) if self.editor_rows.read_only => self.set_message(READ_ONLY_MESSAGE),
+                    Some(file_format) => {
+                        if self.editor_rows.file_format != file_format {
+                            self.editor_rows.file_format = file_format;
+                            self.editor_rows.dirty += 1;
+                        }
+                    }
+                    None => self.set_message(&format!("E474: Invalid argument: {}", option))
+                }
+            }
+            "list" => self.config.list = true,
+            "nolist" => self.config.list = false,
+            "list!" | "invlist" => self.config.list = !self.config.list,
+            "magic" | "regex" => self.config.regex = true,
+            "nomagic" | "noregex" => self.config.regex = false,
+            "readonly" | "ro" => self.editor_rows.read_only = true,
+            "noreadonly" | "noro" => self.editor_rows.read_only = false,
+            _ => self.set_message(&format!("E518: Unknown option: {}", option))
+        }
+    }
+
+    fn selection(&self, mode: Mode) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.visual_anchor?;
+        let cursor = self.cursor_position();
+        match mode {
+            Mode::Visual | Mode::VisualBlock => {
+                Some((cmp::min(anchor, cursor), cmp::max(anchor, cursor)))
+            }
+            Mode::VisualLine => {
+                let start_row = cmp::min(anchor.0, cursor.0);
+                let end_row = cmp::max(anchor.0, cursor.0);
+                Some(((start_row, 0), (end_row, self.editor_rows.row_len(end_row))))
+            }
+            _ => None
+        }
+    }
+
+    fn block(&self) -> Option<(Range<usize>, Range<usize>)> {
+        let anchor = self.visual_anchor?;
+        let cursor = self.cursor_position();
+        let rows = cmp::min(anchor.0, cursor.0)..cmp::max(anchor.0, cursor.0) + 1;
+        let (start, end) = [anchor, cursor]
+            .iter()
+            .map(|&(row, at)| {
+                let start = self.editor_rows.cx_to_rx(row, at);
+                (start, cmp::max(self.editor_rows.cx_to_rx(row, at + 1), start + 1))
+            })
+            .fold((usize::MAX, 0), |(from, to), (start, end)| {
+                (cmp::min(from, start), cmp::max(to, end))
+            });
+        Some((rows, start..end))
+    }
+
+    fn block_text(&self, row: usize, columns: &Range<usize>) -> (usize, usize) {
+        (
+            self.editor_rows.rx_to_cx(row, columns.start),
+            self.editor_rows.rx_to_cx(row, columns.end)
+        )
+    }
+
+    fn block_register(&self, rows: &Range<usize>, columns: &Range<usize>) -> Register {
+        let contents = rows
+            .clone()
+            .map(|row| {
+                let (from, to) = self.block_text(row, columns);
+                self.editor_rows.slice(row, from, to).to_string()
+            })
+            .collect();
+        Register {
+            contents,
+            linewise: false
+        }
+    }
+
+    fn yank_block(&mut self) -> Option<Register> {
+        let (rows, columns) = self.block()?;
+        let register = self.block_register(&rows, &columns);
+        let start = self.block_text(rows.start, &columns).0;
+        self.set_cursor_position((rows.start, start));
+        Some(register)
+    }
+
+    fn delete_block(&mut self) -> Option<Register> {
+        let (rows, columns) = self.block()?;
+        let register = self.block_register(&rows, &columns);
+        for row in rows.clone() {
+            let (from, to) = self.block_text(row, &columns);
+            if from < to {
+                self.editor_rows.delete_range((row, from), (row, to));
+            }
+        }
+        let start = self.block_text(rows.start, &columns).0;
+        self.set_cursor_position((rows.start, start));
+        Some(register)
+    }
+
+    fn pad_row(&mut self, row: usize, column: usize) {
+        let row_len = self.editor_rows.row_len(row);
+        let width = self.editor_rows.cx_to_rx(row, row_len);
+        if width < column {
+            self.editor_rows.insert_text(row, row_len, &" ".repeat(column - width));
+        }
+    }
+
+    fn start_block_insert(&mut self, append: bool) -> Option<BlockInsert> {
+        let (rows, columns) = self.block()?;
+        let column = if append { columns.end } else { columns.start };
+        if append {
+            self.pad_row(rows.start, column);
+        }
+        let start = (rows.start, self.editor_rows.rx_to_cx(rows.start, column));
+        (self.cursor_controller.cursor_y, self.cursor_controller.cursor_x) = start;
+        self.visual_anchor = None;
+        Some(BlockInsert {
+            rows,
+            column,
+            start,
+            append
+        })
+    }
+
+    fn finish_block_insert(&mut self, block: BlockInsert) {
+        let (row, at) = block.start;
+        let (cursor_y, cursor_x) = self.cursor_position();
+        if cursor_y == row && cursor_x > at {
+            let text = self.editor_rows.slice(row, at, cursor_x).to_string();
+            for row in block.rows.start + 1..block.rows.end {
+                let row_len = self.editor_rows.row_len(row);
+                if block.append {
+                    self.pad_row(row, block.column);
+                } else if self.editor_rows.cx_to_rx(row, row_len) < block.column {
+                    continue;
+                }
+                let at = self.editor_rows.rx_to_cx(row, block.column);
+                self.editor_rows.insert_text(row, at, &text);
+            }
+        }
+        self.set_cursor_position(block.start);
+    }
+
+    fn selected_columns(&self, mode: Mode, file_row: usize) -> Option<(usize, usize)> {
+        if mode == Mode::VisualBlock {
+            let (rows, columns) = self.block()?;
+            let row_len = self.editor_rows.row_len(file_row);
+            let width = self.editor_rows.cx_to_rx(file_row, row_len);
+            return (rows.contains(&file_row) && width > columns.start)
+                .then(|| (columns.start, cmp::min(columns.end, width)));
+        }
+        let (start, end) = self.selection(mode)?;
+        if file_row < start.0 || file_row > end.0 {
+            return None;
+        }
+        let row_len = self.editor_rows.row_len(file_row);
+        let from = if file_row == start.0 { start.1 } else { 0 };
+        let to = if file_row == end.0 { end.1 + 1 } else { row_len + 1 };
+        let render_to = match to > row_len {
+            true => self.editor_rows.cx_to_rx(file_row, row_len) + 1,
+            false => self.editor_rows.cx_to_rx(file_row, to)
+        };
+        Some((self.editor_rows.cx_to_rx(file_row, from), render_to))
+    }
+
+    fn matched_columns(&self, file_row: usize) -> Option<(usize, usize)> {
+        let ((row, at), len) = self.search_state.highlighted_match?;
+        (row == file_row).then(|| {
+            (
+                self.editor_rows.cx_to_rx(row, at),
+                self.editor_rows.cx_to_rx(row, at + len)
+            )
+        })
+    }
+
+    fn draw_row(
+        &mut self,
+        file_row: usize,
+        (column_offset, screen_columns): (usize, usize),
+        selected: Option<(usize, usize)>
+    ) {
+        self.editor_rows.update_highlight(file_row);
+        let listed = match self.config.list {
+            true => self.editor_rows.list_render(file_row, &self.config.listchars),
+            false => None
+        };
+        let (render, highlight) = match &listed {
+            Some((render, highlight)) => (render.as_str(), highlight.as_slice()),
+            None => (
+                self.editor_rows.get_render(file_row).unwrap_or_default(),
+                self.editor_rows.get_highlight(file_row)
+            )
+        };
+        let render_len = render.chars().map(Row::char_width).sum();
+        let line_end = selected.map_or(render_len, |(_, to)| cmp::max(render_len, to));
+        let line_end = cmp::min(line_end, column_offset + screen_columns);
+        let matched = self.matched_columns(file_row);
+        let bracket = self
+            .bracket_match
+            .filter(|&(row, _)| row == file_row)
+            .map(|(_, at)| self.editor_rows.cx_to_rx(file_row, at));
+        let cells = render
+            .char_indices()
+            .zip(highlight.iter().copied())
+            .chain(iter::repeat(((render.len(), ' '), HighlightType::Normal)));
+        let mut render_x = 0;
+        let mut reversed = false;
+        let mut current_color = HighlightType::Normal.color();
+        let mut run_start = None;
+        let mut run_end = 0;
+        for ((index, ch), highlight) in cells {
+            let width = Row::char_width(ch);
+            let next_render_x = render_x + width;
+            if render_x >= line_end || next_render_x > column_offset + screen_columns {
+                break;
+            }
+            if next_render_x <= column_offset {
+                render_x = next_render_x;
+                continue;
+            }
+            let is_match = matched.is_some_and(|(from, to)| from <= render_x && render_x < to);
+            let color = match is_match {
+                true => HighlightType::Match,
+                false if bracket == Some(render_x) => HighlightType::MatchBracket,
+                false => highlight
+            }
+            .color();
+            let is_selected = selected.is_some_and(|(from, to)| from <= render_x && render_x < to);
+            let in_run = index < render.len() && render_x >= column_offset;
+            if color != current_color || is_selected != reversed || !in_run {
+                if let Some(start) = run_start.take() {
+                    self.editor_contents.push_str(&render[start..run_end]);
+                }
+            }
+            if color != current_color {
+                queue!(self.editor_contents, style::SetForegroundColor(color)).unwrap();
+                current_color = color;
+            }
+            if is_selected != reversed {
+                let attribute = match is_selected {
+                    true => style::Attribute::Reverse,
+                    false => style::Attribute::NoReverse
+                };
+                queue!(self.editor_contents, style::SetAttribute(attribute)).unwrap();
+                reversed = is_selected;
+            }
+            if render_x < column_offset {
+                self.editor_contents.push_spaces(next_render_x - column_offset);
+            } else if in_run {
+                run_start.get_or_insert(index);
+                run_end = index + ch.len_utf8();
+            } else {
+                self.editor_contents.push(' ');
+            }
+            render_x = next_render_x;
+        }
+        if let Some(start) = run_start {
+            self.editor_contents.push_str(&render[start..run_end]);
+        }
+        if reversed {
+            queue!(self.editor_contents, style::SetAttribute(style::Attribute::NoReverse)).unwrap();
+        }
+        if current_color != HighlightType::Normal.color() {
+            let color = HighlightType::Normal.color();
+            queue!(self.editor_contents, style::SetForegroundColor(color)).unwrap();
+        }
+    }
+
+    fn draw_rows(&mut self, mode: Mode) {
+        let (x, y) = self.windows[self.current_window].origin;
+        let (columns, screen_rows) = self.windows[self.current_window].size;
+        let gutter_width = self.gutter_width();
+        let screen_columns = columns.saturating_sub(gutter_width);
+        let row_offset = self.cursor_controller.row_offset;
+        self.bracket_match = self
+            .editor_rows
+            .bracket_partner(self.cursor_position(), row_offset..row_offset + screen_rows);
+        let mut file_row = row_offset;
+        let mut sub_row = 0;
+        for i in 0..screen_rows {
+            self.editor_contents.move_to(x, y + i);
+            let column_offset = match self.config.wrap {
+                true => sub_row * screen_columns,
+                false => self.cursor_controller.column_offset
+            };
+            if gutter_width > 0 && file_row < self.editor_rows.number_of_rows() && sub_row == 0 {
+                let line_number = self.line_number(file_row);
+                write!(self.editor_contents, "{:>1$} ", line_number, gutter_width - 1).unwrap();
+            } else {
+                self.editor_contents.push_spaces(gutter_width);
+            }
+            let cursor_line =
+                self.config.cursor_line && file_row == self.cursor_controller.cursor_y;
+            if cursor_line {
+                let color = CURSOR_LINE_BACKGROUND;
+                queue!(self.editor_contents, style::SetBackgroundColor(color)).unwrap();
+            }
+            if let Some(fold) = self.editor_rows.closed_fold(file_row) {
+                let summary = format!(
+                    "+--{:>3} lines: {}",
+                    fold.len(),
+                    self.editor_rows.get_row(file_row).unwrap_or_default().trim()
+                );
+                let color = HighlightType::Fold.color();
+                queue!(self.editor_contents, style::SetForegroundColor(color)).unwrap();
+                self.editor_contents.push_str(truncate_chars(&summary, screen_columns));
+                let color = HighlightType::Normal.color();
+                queue!(self.editor_contents, style::SetForegroundColor(color)).unwrap();
+            } else if file_row < self.editor_rows.number_of_rows() {
+                let selected = self.selected_columns(mode, file_row);
+                self.draw_row(file_row, (column_offset, screen_columns), selected);
+            } else if self.editor_rows.number_of_rows() == 0 && i == screen_rows / 3 {
+                let mut welcome = "Rusty vim --- Version 0.1.1".to_string();
+                welcome.truncate(screen_columns.saturating_sub(1));
+                let padding = screen_columns.saturating_sub(welcome.len()) / 2;
+                self.editor_contents.push('~');
+                self.editor_contents.push_spaces(padding.saturating_sub(1));
+                self.editor_contents.push_str(&welcome);
+            } else {
+                self.editor_contents.push('~');
+            }
+            queue!(
+                self.editor_contents,
+                terminal::Clear(terminal::ClearType::UntilNewLine)
+                )
+                .unwrap();
+            if cursor_line {
+                let color = style::Color::Reset;
+                queue!(self.editor_contents, style::SetBackgroundColor(color)).unwrap();
+            }
+            if file_row < self.editor_rows.number_of_rows() {
+                let columns = (column_offset, screen_columns);
+                self.draw_color_columns(file_row, (x + gutter_width, y + i), columns);
+            }
+            sub_row += 1;
+            if sub_row >= self.cursor_controller.row_height(file_row, &self.editor_rows) {
+                file_row = self.editor_rows.next_visible_row(file_row);
+                sub_row = 0;
+            }
+        }
+    }
+
+    fn draw_color_columns(
+        &mut self,
+        file_row: usize,
+        (x, y): (usize, usize),
+        (column_offset, screen_columns): (usize, usize)
+    ) {
+        if self.editor_rows.closed_fold(file_row).is_some() {
+            return;
+        }
+        for index in 0..self.config.color_columns.len() {
+            let render_x = self.config.color_columns[index] - 1;
+            if render_x < column_offset || render_x >= column_offset + screen_columns {
+                continue;
+            }
+            let Some((ch, highlight)) = self.editor_rows.cell_at(file_row, render_x) else {
+                continue;
+            };
+            self.editor_contents.move_to(x + render_x - column_offset, y);
+            queue!(
+                self.editor_contents,
+                style::SetBackgroundColor(COLOR_COLUMN_BACKGROUND),
+                style::SetForegroundColor(highlight.color())
+            )
+            .unwrap();
+            self.editor_contents.push(ch);
+            queue!(self.editor_contents, style::ResetColor).unwrap();
+        }
+    }
+
+    fn draw_border(&mut self) {
+        let (x, y) = self.windows[self.current_window].origin;
+        let (columns, rows) = self.windows[self.current_window].size;
+        for i in 0..rows {
+            self.editor_contents.move_to(x + columns, y + i);
+            self.editor_contents.push('|');
+        }
+    }
+
+    fn status_info(&self, mode: Mode) -> (String, String) {
+        let filename = self.editor_rows.display_name();
+        let info = format!(
+            "{} {}{}{} - {} lines",
+            mode.label(),
+            filename,
+            if self.editor_rows.read_only { " [RO]" } else { "" },
+            if self.editor_rows.file_format == FileFormat::Dos { " [dos]" } else { "" },
+            self.editor_rows.number_of_rows()
+        );
+        let mut line_info = format!(
+            "{} | {}:{}",
+            self.editor_rows.syntax.name(),
+            self.cursor_controller.cursor_y + 1,
+            self.cursor_controller.cursor_x + 1
+        );
+        if self.config.ruler {
+            let position = self.cursor_controller.scroll_position(&self.editor_rows);
+            line_info.push_str(&format!(" {:>4}", position));
+        }
+        (info, line_info)
+    }
+
+    fn draw_status_bar(&mut self, mode: Mode, (x, y): (usize, usize), screen_columns: usize) {
+        self.editor_contents.move_to(x, y);
+        let (info, line_info) = self.status_info(mode);
+        let info = truncate_chars(&info, screen_columns);
+        queue!(self.editor_contents, style::SetAttribute(style::Attribute::Reverse)).unwrap();
+        self.editor_contents.push_str(info);
+        let width = info.chars().count();
+        let line_info_width = line_info.chars().count();
+        match screen_columns - width >= line_info_width {
+            true => {
+                self.editor_contents
+                    .push_spaces(screen_columns - width - line_info_width);
+                self.editor_contents.push_str(&line_info);
+            }
+            false => self.editor_contents.push_spaces(screen_columns - width)
+        }
+        queue!(self.editor_contents, style::SetAttribute(style::Attribute::Reset)).unwrap();
+    }
+
+    fn draw_message_bar(&mut self) {
+        self.editor_contents.move_to(0, self.win_size.1 + 1);
+        queue!(
+            self.editor_contents,
+            terminal::Clear(terminal::ClearType::UntilNewLine)
+        )
+        .unwrap();
+        if let Some(msg) = self.status_message.message() {
+            self.editor_contents
+                .push_str(truncate_chars(msg, self.win_size.0));
+        }
+    }
+
+    fn refresh_screen(&mut self, mode: Mode) -> crossterm::Result<()> {
+        let active = self.current_window;
+        self.editor_contents.reserve_frame(self.win_size);
+        for index in 0..self.windows.len() {
+            self.focus_window(index);
+            if index != active {
+                self.set_cursor_position(self.cursor_position());
+            }
+            self.fit_window();
+            self.cursor_controller.scroll(&self.editor_rows);
+            let window_mode = if index == active { mode } else { Mode::Normal };
+            self.draw_rows(window_mode);
+            let (x, y) = self.windows[index].origin;
+            let (columns, rows) = self.windows[index].size;
+            match self.split {
+                Split::Horizontal => self.draw_status_bar(window_mode, (x, y + rows), columns),
+                Split::Vertical if index + 1 < self.windows.len() => self.draw_border(),
+                Split::Vertical => {}
+            }
+        }
+        self.focus_window(active);
+        if self.split == Split::Vertical {
+            self.draw_status_bar(mode, (0, self.win_size.1), self.win_size.0);
+        }
+        self.draw_message_bar();
+        let (x, y) = self.windows[active].origin;
+        let position = self.cursor_controller.cursor_screen_position(&self.editor_rows);
+        if let Some((cursor_x, cursor_y)) = position {
+            self.editor_contents
+                .set_cursor(cursor_x + self.gutter_width() + x, cursor_y + y);
+        }
+        self.needs_redraw = false;
+        self.editor_contents.flush()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DiskChange {
+    Unchanged,
+    Modified,
+    Deleted
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FileFormat {
+    Unix,
+    Dos
+}
+
+impl FileFormat {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            _ => None
+        }
+    }
+
+    fn detect(contents: &str) -> Self {
+        let line_feeds = contents.matches('\n').count();
+        match line_feeds > 0 && contents.matches("\r\n").count() == line_feeds {
+            true => FileFormat::Dos,
+            false => FileFormat::Unix
+        }
+    }
+
+    fn line_ending(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n"
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LineNumbers {
+    Off,
+    Absolute,
+    Relative,
+    Hybrid
+}
+
+impl LineNumbers {
+    fn from_flags(number: bool, relative: bool) -> Self {
+        match (number, relative) {
+            (false, false) => LineNumbers::Off,
+            (true, false) => LineNumbers::Absolute,
+            (false, true) => LineNumbers::Relative,
+            (true, true) => LineNumbers::Hybrid
+        }
+    }
+
+    fn number(self) -> bool {
+        matches!(self, LineNumbers::Absolute | LineNumbers::Hybrid)
+    }
+
+    fn relative(self) -> bool {
+        matches!(self, LineNumbers::Relative | LineNumbers::Hybrid)
+    }
+
+    fn with_number(self, number: bool) -> Self {
+        Self::from_flags(number, self.relative())
+    }
+
+    fn with_relative(self, relative: bool) -> Self {
+        Self::from_flags(self.number(), relative)
+    }
+}
+
+struct StatusMessage {
+    message: Option<String>,
+    set_time: Option<Instant>
+}
+
+impl StatusMessage {
+    fn new(initial_message: String) -> Self {
+        Self {
+            message: Some(initial_message),
+            set_time: Some(Instant::now())
+        }
+    }
+
+    fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+        self.set_time = Some(Instant::now())
+    }
+
+    fn is_expired(&self) -> bool {
+        self.set_time
+            .is_some_and(|time| time.elapsed() > Duration::from_secs(5))
+    }
+
+    fn message(&mut self) -> Option<&String> {
+        self.set_time.and_then(|time| {
+            if time.elapsed() > Duration::from_secs(5) {
+                self.message = None;
+                self.set_time = None;
+                None
+            } else {
+                Some(self.message.as_ref().unwrap())
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SearchDirection {
+    Forward,
+    Backward
+}
+
+impl SearchDirection {
+    fn reverse(self) -> Self {
+        match self {
+            SearchDirection::Forward => SearchDirection::Backward,
+            SearchDirection::Backward => SearchDirection::Forward
+        }
+    }
+}
+
+struct Buffer {
+    editor_rows: EditorRows,
+    cursor_controller: CursorController
+}
+
+impl Buffer {
+    fn new(editor_rows: EditorRows, win_size: (usize, usize)) -> Self {
+        Self {
+            editor_rows,
+            cursor_controller: CursorController::new(win_size)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Split {
+    Horizontal,
+    Vertical
+}
+
+fn split_sizes(total: usize, count: usize) -> Vec<usize> {
+    let available = total.saturating_sub(count.saturating_sub(1));
+    (0..count)
+        .map(|index| available / count + usize::from(index < available % count))
+        .collect()
+}
+
+struct Window {
+    buffer: usize,
+    cursor_controller: CursorController,
+    origin: (usize, usize),
+    size: (usize, usize)
+}
+
+impl Window {
+    fn new(buffer: usize, win_size: (usize, usize)) -> Self {
+        Self {
+            buffer,
+            cursor_controller: CursorController::new(win_size),
+            origin: (0, 0),
+            size: win_size
+        }
+    }
+
+    fn contains(&self, (column, row): (usize, usize)) -> bool {
+        (self.origin.0..self.origin.0 + self.size.0).contains(&column)
+            && (self.origin.1..self.origin.1 + self.size.1).contains(&row)
+    }
+}
+
+struct JumpList {
+    positions: Vec<(usize, usize)>,
+    index: usize
+}
+
+impl JumpList {
+    fn new() -> Self {
+        Self {
+            positions: Vec::new(),
+            index: 0
+        }
+    }
+
+    fn record(&mut self, position: (usize, usize)) {
+        self.positions.truncate(self.index);
+        self.positions.retain(|&(row, _)| row != position.0);
+        self.positions.push(position);
+        if self.positions.len() > JUMP_LIST_SIZE {
+            self.positions.remove(0);
+        }
+        self.index = self.positions.len();
+    }
+
+    fn back(&mut self, current: (usize, usize)) -> Option<(usize, usize)> {
+        if self.index == 0 {
+            return None;
+        }
+        if self.index == self.positions.len() {
+            self.positions.push(current);
+        }
+        self.index -= 1;
+        Some(self.positions[self.index])
+    }
+
+    fn forward(&mut self) -> Option<(usize, usize)> {
+        if self.index + 1 >= self.positions.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.positions[self.index])
+    }
+}
+
+struct SearchState {
+    query: String,
+    direction: SearchDirection,
+    last_match: Option<(usize, usize)>,
+    highlighted_match: Option<((usize, usize), usize)>
+}
+
+impl SearchState {
+    fn new() -> Self {
+        Self {
+            query: String::new(),
+            direction: SearchDirection::Forward,
+            last_match: None,
+            highlighted_match: None
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CursorController {
+    cursor_x: usize,
+    cursor_y: usize,
+    screen_columns: usize,
+    screen_rows: usize,
+    row_offset: usize,
+    column_offset: usize,
+    goal_column: Option<(usize, (usize, usize))>,
+    render_x: usize,
+    detached: bool,
+    wrap: bool,
+    scroll_off: usize
+}
+
+impl CursorController {
+    fn new(win_size: (usize, usize)) -> CursorController {
+        Self {
+            cursor_x: 0,
+            cursor_y: 0,
+            screen_columns: win_size.0,
+            screen_rows: win_size.1,
+            row_offset: 0,
+            column_offset: 0,
+            goal_column: None,
+            render_x: 0,
+            detached: false,
+            wrap: false,
+            scroll_off: 0
+        }
+    }
+
+    fn goal_column(&self, editor_rows: &EditorRows) -> usize {
+        match self.goal_column {
+            Some((column, position)) if position == (self.cursor_y, self.cursor_x) => column,
+            _ => editor_rows.cx_to_rx(self.cursor_y, self.cursor_x)
+        }
+    }
+
+    fn goto_row(&mut self, row: usize, editor_rows: &EditorRows) {
+        let goal = self.goal_column(editor_rows);
+        self.cursor_y =
+            editor_rows.visible_row(cmp::min(row, editor_rows.number_of_rows().saturating_sub(1)));
+        self.cursor_x = cmp::min(
+            editor_rows.rx_to_cx(self.cursor_y, goal),
+            editor_rows.row_len(self.cursor_y).saturating_sub(1)
+        );
+        self.goal_column = Some((goal, (self.cursor_y, self.cursor_x)));
+    }
+
+    fn scroll_view(&mut self, lines: isize, editor_rows: &EditorRows) {
+        let max_offset = editor_rows.number_of_rows().saturating_sub(self.screen_rows);
+        self.row_offset = cmp::min(self.row_offset.saturating_add_signed(lines), max_offset);
+        self.detached = true;
+    }
+
+    fn row_height(&self, row: usize, editor_rows: &EditorRows) -> usize {
+        if !self.wrap || editor_rows.closed_fold(row).is_some() {
+            return 1;
+        }
+        let width = editor_rows.cx_to_rx(row, editor_rows.row_len(row));
+        cmp::max(width.div_ceil(self.screen_columns), 1)
+    }
+
+    fn screen_rows_between(&self, from: usize, to: usize, editor_rows: &EditorRows) -> usize {
+        let mut row = from;
+        let mut count = 0;
+        while row < to {
+            count += self.row_height(row, editor_rows);
+            row = editor_rows.next_visible_row(row);
+        }
+        count
+    }
+
+    fn scroll_position(&self, editor_rows: &EditorRows) -> String {
+        let rows = editor_rows.number_of_rows();
+        let mut row = self.row_offset;
+        let mut used = 0;
+        while row < rows && used <= self.screen_rows {
+            used += self.row_height(row, editor_rows);
+            row = editor_rows.next_visible_row(row);
+        }
+        match (self.row_offset == 0, row >= rows && used <= self.screen_rows) {
+            (true, true) => "All".into(),
+            (true, false) => "Top".into(),
+            (false, true) => "Bot".into(),
+            (false, false) => format!("{}%", self.cursor_y * 100 / cmp::max(rows - 1, 1))
+        }
+    }
+
+    fn cursor_sub_row(&self, editor_rows: &EditorRows) -> (usize, usize) {
+        if !self.wrap {
+            return (0, self.render_x - self.column_offset);
+        }
+        let height = self.row_height(self.cursor_y, editor_rows);
+        let sub_row = cmp::min(self.render_x / self.screen_columns, height - 1);
+        let column = self.render_x - sub_row * self.screen_columns;
+        (sub_row, cmp::min(column, self.screen_columns - 1))
+    }
+
+    fn cursor_screen_position(&self, editor_rows: &EditorRows) -> Option<(usize, usize)> {
+        if self.cursor_y < self.row_offset {
+            return None;
+        }
+        let (sub_row, column) = self.cursor_sub_row(editor_rows);
+        let row = self.screen_rows_between(self.row_offset, self.cursor_y, editor_rows) + sub_row;
+        (row < self.screen_rows).then_some((column, row))
+    }
+
+    fn screen_to_buffer(
+        &self,
+        (column, row): (usize, usize),
+        gutter_width: usize,
+        editor_rows: &EditorRows
+    ) -> (usize, usize) {
+        let last_row = editor_rows.number_of_rows().saturating_sub(1);
+        let mut file_row = self.row_offset;
+        let mut sub_row = row;
+        while file_row < last_row && sub_row >= self.row_height(file_row, editor_rows) {
+            sub_row -= self.row_height(file_row, editor_rows);
+            file_row = editor_rows.next_visible_row(file_row);
+        }
+        let file_row = cmp::min(file_row, last_row);
+        let sub_row = match self.wrap {
+            true => cmp::min(sub_row, self.row_height(file_row, editor_rows) - 1),
+            false => 0
+        };
+        let render_x = column.saturating_sub(gutter_width)
+            + self.column_offset
+            + sub_row * self.screen_columns;
+        (file_row, editor_rows.rx_to_cx(file_row, render_x))
+    }
+
+    fn scroll(&mut self, editor_rows: &EditorRows) {
+        self.cursor_y = editor_rows.visible_row(self.cursor_y);
+        self.row_offset = editor_rows.visible_row(self.row_offset);
+        self.render_x = editor_rows.cx_to_rx(self.cursor_y, self.cursor_x);
+        if self.detached {
+            return;
+        }
+        if self.wrap {
+            self.column_offset = 0;
+        } else {
+            self.column_offset = cmp::min(self.column_offset, self.render_x);
+            if self.render_x >= self.column_offset + self.screen_columns {
+                self.column_offset = self.render_x + 1 - self.screen_columns;
+            }
+        }
+        let margin = cmp::min(self.scroll_off, self.screen_rows.saturating_sub(1) / 2);
+        let upper = (0..margin).fold(self.cursor_y, |row, _| editor_rows.previous_visible_row(row));
+        self.row_offset = cmp::min(self.row_offset, upper);
+        let mut top = self.cursor_y;
+        let mut used = self.cursor_sub_row(editor_rows).0 + 1;
+        let mut below = self.cursor_y;
+        for _ in 0..margin {
+            let next = editor_rows.next_visible_row(below);
+            if next >= editor_rows.number_of_rows() {
+                break;
+            }
+            if below == self.cursor_y {
+                used = self.row_height(below, editor_rows);
+            }
+            used += self.row_height(next, editor_rows);
+            below = next;
+        }
+        while top > self.row_offset {
+            let previous = editor_rows.previous_visible_row(top);
+            used += self.row_height(previous, editor_rows);
+            if used > self.screen_rows {
+                break;
+            }
+            top = previous;
+        }
+        self.row_offset = top;
+    }
+
+    fn move_cursor(&mut self, direction: char, editor_rows: &EditorRows) {
+        match direction {
+            'j' => self.goto_row(editor_rows.next_visible_row(self.cursor_y), editor_rows),
+            'h' => {
+                self.cursor_x = self.cursor_x.saturating_sub(1);
+            }
+            'k' => self.goto_row(editor_rows.previous_visible_row(self.cursor_y), editor_rows),
+            'l' => {
+                let row_len = editor_rows.row_len(self.cursor_y);
+                if self.cursor_x + 1 < row_len {
+                    self.cursor_x += 1;
+                }
+            }
+            'w' => self.move_word_forward(editor_rows),
+            'b' => self.move_word_backward(editor_rows),
+            'e' => self.move_word_end(editor_rows),
+            '0' => self.cursor_x = 0,
+            '{' => {
+                (self.cursor_y, self.cursor_x) = editor_rows.previous_paragraph(self.cursor_y)
+            }
+            '}' => (self.cursor_y, self.cursor_x) = editor_rows.next_paragraph(self.cursor_y),
+            '(' => {
+                (self.cursor_y, self.cursor_x) =
+                    editor_rows.previous_sentence((self.cursor_y, self.cursor_x))
+            }
+            ')' => {
+                (self.cursor_y, self.cursor_x) =
+                    editor_rows.next_sentence((self.cursor_y, self.cursor_x))
+            }
+            '^' => {
+                self.cursor_x = cmp::min(
+                    editor_rows.first_non_blank(self.cursor_y),
+                    editor_rows.row_len(self.cursor_y).saturating_sub(1)
+                )
+            }
+            '$' => {
+                self.cursor_x = editor_rows.row_len(self.cursor_y).saturating_sub(1);
+                self.goal_column = Some((usize::MAX, (self.cursor_y, self.cursor_x)));
+            }
+            'G' => self.goto_row(editor_rows.number_of_rows().saturating_sub(1), editor_rows),
+            _ => {}
+        }
+    }
+
+    fn move_word_forward(&mut self, editor_rows: &EditorRows) {
+        match editor_rows.next_word_start((self.cursor_y, self.cursor_x)) {
+            Some(pos) => (self.cursor_y, self.cursor_x) = pos,
+            None => self.move_to_buffer_end(editor_rows)
+        }
+    }
+
+    fn move_word_end(&mut self, editor_rows: &EditorRows) {
+        let mut pos = (self.cursor_y, self.cursor_x);
+        loop {
+            match editor_rows.next_position(pos) {
+                Some(next) => pos = next,
+                None => {
+                    self.move_to_buffer_end(editor_rows);
+                    return;
+                }
+            }
+            if editor_rows.char_class_at(pos) != CharClass::Whitespace {
+                break;
+            }
+        }
+        let class = editor_rows.char_class_at(pos);
+        while let Some(next) = editor_rows.next_position(pos) {
+            if editor_rows.char_class_at(next) != class {
+                break;
+            }
+            pos = next;
+        }
+        (self.cursor_y, self.cursor_x) = pos;
+    }
+
+    fn move_word_backward(&mut self, editor_rows: &EditorRows) {
+        let mut pos = (self.cursor_y, self.cursor_x);
+        loop {
+            match editor_rows.previous_position(pos) {
+                Some(previous) => pos = previous,
+                None => {
+                    (self.cursor_y, self.cursor_x) = (0, 0);
+                    return;
+                }
+            }
+            if editor_rows.row_len(pos.0) == 0 {
+                (self.cursor_y, self.cursor_x) = pos;
+                return;
+            }
+            if editor_rows.char_class_at(pos) != CharClass::Whitespace {
+                break;
+            }
+        }
+        let class = editor_rows.char_class_at(pos);
+        while let Some(previous) = editor_rows.previous_position(pos) {
+            if editor_rows.char_class_at(previous) != class {
+                break;
+            }
+            pos = previous;
+        }
+        (self.cursor_y, self.cursor_x) = pos;
+    }
+
+    fn page(&mut self, direction: KeyCode, editor_rows: &EditorRows) {
+        let screen_rows = cmp::max(self.screen_rows, 1);
+        if direction == KeyCode::PageUp {
+            self.goto_row(self.row_offset.saturating_sub(screen_rows), editor_rows);
+        } else {
+            self.goto_row(self.row_offset + 2 * screen_rows - 1, editor_rows);
+        }
+    }
+
+    fn half_page(&mut self, down: bool, editor_rows: &EditorRows) {
+        let half = cmp::max(self.screen_rows / 2, 1);
+        if down {
+            let max_offset = editor_rows.number_of_rows().saturating_sub(self.screen_rows);
+            self.row_offset = cmp::min(self.row_offset + half, max_offset);
+            self.goto_row(self.cursor_y + half, editor_rows);
+        } else {
+            self.row_offset = self.row_offset.saturating_sub(half);
+            self.goto_row(self.cursor_y.saturating_sub(half), editor_rows);
+        }
+    }
+
+    fn move_to_buffer_end(&mut self, editor_rows: &EditorRows) {
+        self.cursor_y = editor_rows.number_of_rows().saturating_sub(1);
+        self.cursor_x = editor_rows.row_len(self.cursor_y).saturating_sub(1);
+    }
+}
+
+fn modified_time(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn closing_pair(ch: char) -> Option<char> {
+    match ch {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' | '\'' => Some(ch),
+        _ => None
+    }
+}
+
+fn balance(
+    mut chars: impl Iterator<Item = ((usize, usize), char)>,
+    bracket: char,
+    partner: char
+) -> Option<(usize, usize)> {
+    let mut depth = 0usize;
+    chars.find_map(|(position, ch)| {
+        if ch == bracket {
+            depth += 1;
+        } else if ch == partner {
+            depth -= 1;
+        }
+        (depth == 0).then_some(position)
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CharClass {
+    Whitespace,
+    Punctuation,
+    Word
+}
+
+impl CharClass {
+    fn of(ch: char) -> Self {
+        if ch.is_whitespace() {
+            CharClass::Whitespace
+        } else if ch.is_alphanumeric() || ch == '_' {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+struct Row {
+    row_content: String,
+    render: String,
+    highlight: Option<Vec<HighlightType>>
+}
+
+impl Row {
+    fn new(row_content: String, tab_stop: usize) -> Self {
+        let mut row = Self {
+            row_content,
+            render: String::new(),
+            highlight: None
+        };
+        row.update_render(tab_stop);
+        row
+    }
+
+    fn update_render(&mut self, tab_stop: usize) {
+        self.highlight = None;
+        self.render.clear();
+        let mut render_x = 0;
+        for ch in self.row_content.chars() {
+            if ch == '\t' {
+                let spaces = tab_stop - render_x % tab_stop;
+                (0..spaces).for_each(|_| self.render.push(' '));
+                render_x += spaces;
+            } else {
+                self.render.push(ch);
+                render_x += Self::char_width(ch);
+            }
+        }
+    }
+
+    fn list_render(&self, tab_stop: usize, listchars: &ListChars) -> (String, Vec<HighlightType>) {
+        let mut highlight = self.highlight.as_deref().unwrap_or_default().iter().copied();
+        let mut next_highlight = || highlight.next().unwrap_or(HighlightType::Normal);
+        let trailing = self.row_content.trim_end_matches([' ', '\t']).len();
+        let mut render = String::new();
+        let mut colors = Vec::new();
+        let mut render_x = 0;
+        for (index, ch) in self.row_content.char_indices() {
+            match (ch, listchars.tab, listchars.trail) {
+                ('\t', Some((first, fill)), _) => {
+                    let spaces = tab_stop - render_x % tab_stop;
+                    for cell in 0..spaces {
+                        next_highlight();
+                        render.push(if cell == 0 { first } else { fill });
+                        colors.push(HighlightType::Whitespace);
+                    }
+                    render_x += spaces;
+                }
+                ('\t', None, _) => {
+                    let spaces = tab_stop - render_x % tab_stop;
+                    for _ in 0..spaces {
+                        render.push(' ');
+                        colors.push(next_highlight());
+                    }
+                    render_x += spaces;
+                }
+                (' ', _, Some(trail)) if index >= trailing => {
+                    next_highlight();
+                    render.push(trail);
+                    colors.push(HighlightType::Whitespace);
+                    render_x += 1;
+                }
+                _ => {
+                    render.push(ch);
+                    colors.push(next_highlight());
+                    render_x += Self::char_width(ch);
+                }
+            }
+        }
+        if let Some(eol) = listchars.eol {
+            render.push(eol);
+            colors.push(HighlightType::Whitespace);
+        }
+        (render, colors)
+    }
+
+    fn char_width(ch: char) -> usize {
+        ch.width().unwrap_or(1)
+    }
+
+    fn len(&self) -> usize {
+        self.row_content.chars().count()
+    }
+
+    fn byte_index(&self, at: usize) -> usize {
+        self.row_content
+            .char_indices()
+            .nth(at)
+            .map_or(self.row_content.len(), |(index, _)| index)
+    }
+
+    fn char_index(&self, byte_index: usize) -> usize {
+        self.row_content[..byte_index].chars().count()
+    }
+
+    fn char_range(&self, row: usize, range: Range<usize>) -> ((usize, usize), usize) {
+        let start = self.char_index(range.start);
+        ((row, start), self.row_content[range].chars().count())
+    }
+
+    fn slice(&self, from: usize, to: usize) -> &str {
+        &self.row_content[self.byte_index(from)..self.byte_index(cmp::max(from, to))]
+    }
+
+    fn cx_to_rx(&self, cursor_x: usize, tab_stop: usize) -> usize {
+        self.row_content
+            .chars()
+            .take(cursor_x)
+            .fold(0, |render_x, ch| {
+                if ch == '\t' {
+                    render_x + tab_stop - render_x % tab_stop
+                } else {
+                    render_x + Self::char_width(ch)
+                }
+            })
+    }
+
+    fn rx_to_cx(&self, render_x: usize, tab_stop: usize) -> usize {
+        let mut current_render_x = 0;
+        for (cursor_x, ch) in self.row_content.chars().enumerate() {
+            if ch == '\t' {
+                current_render_x += tab_stop - current_render_x % tab_stop;
+            } else {
+                current_render_x += Self::char_width(ch);
+            }
+            if current_render_x > render_x {
+                return cursor_x;
+            }
+        }
+        self.len()
+    }
+}
+
+struct EditorRows {
+    row_contents: Vec<Row>,
+    filename: Option<PathBuf>,
+    scratch_name: Option<&'static str>,
+    read_only: bool,
+    dirty: usize,
+    swap_found: bool,
+    swapped_at: usize,
+    mtime: Option<SystemTime>,
+    pending_edits: Vec<Edit>,
+    undo_stack: Vec<UndoGroup>,
+    redo_stack: Vec<UndoGroup>,
+    syntax: &'static dyn SyntaxHighlight,
+    tab_stop: usize,
+    marks: HashMap<char, (usize, usize)>,
+    folds: Vec<Range<usize>>,
+    file_format: FileFormat,
+    bom: bool,
+    eol: bool
+}
+
+impl EditorRows {
+    fn new() -> Self {
+        Self {
+            row_contents: Vec::new(),
+            filename: None,
+            scratch_name: None,
+            read_only: false,
+            dirty: 0,
+            swap_found: false,
+            swapped_at: 0,
+            mtime: None,
+            pending_edits: Vec::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            syntax: &NoHighlight,
+            tab_stop: TAB_STOP,
+            marks: HashMap::new(),
+            folds: Vec::new(),
+            file_format: FileFormat::Unix,
+            bom: false,
+            eol: true
+        }
+    }
+
+    fn from_file(file: &Path) -> io::Result<Self> {
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err)
+        };
+        let mut editor_rows = Self {
+            row_contents: Vec::new(),
+            filename: Some(file.to_path_buf()),
+            scratch_name: None,
+            read_only: false,
+            dirty: 0,
+            swap_found: swap::exists(file),
+            swapped_at: 0,
+            mtime: modified_time(file),
+            pending_edits: Vec::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            syntax: syntax::select(file),
+            tab_stop: TAB_STOP,
+            marks: HashMap::new(),
+            folds: Vec::new(),
+            file_format: FileFormat::Unix,
+            bom: false,
+            eol: true
+        };
+        editor_rows.load(&contents);
+        Ok(editor_rows)
+    }
+
+    fn from_reader(mut input: impl io::Read) -> io::Result<Self> {
+        let mut contents = Vec::new();
+        input.read_to_end(&mut contents)?;
+        let mut editor_rows = Self::new();
+        editor_rows.load(&String::from_utf8_lossy(&contents));
+        editor_rows.scratch_name = Some("[stdin]");
+        Ok(editor_rows)
+    }
+
+    fn load(&mut self, contents: &str) {
+        let text = contents.strip_prefix('\u{feff}');
+        self.bom = text.is_some();
+        let text = text.unwrap_or(contents);
+        self.file_format = FileFormat::detect(text);
+        self.eol = text.is_empty() || text.ends_with('\n');
+        self.row_contents = match text.strip_suffix('\n').unwrap_or(text) {
+            "" if text.is_empty() => Vec::new(),
+            text => text
+                .split('\n')
+                .map(|line| match self.file_format {
+                    FileFormat::Dos => line.strip_suffix('\r').unwrap_or(line),
+                    FileFormat::Unix => line
+                })
+                .map(|line| Row::new(line.into(), self.tab_stop))
+                .collect()
+        };
+    }
+
+    fn display_name(&self) -> String {
+        match (&self.filename, self.scratch_name) {
+            (Some(path), _) => path.display().to_string(),
+            (None, Some(name)) => name.into(),
+            (None, None) => "[No Name]".into()
+        }
+    }
+
+    fn set_filename(&mut self, file: PathBuf) {
+        self.scratch_name = None;
+        self.syntax = syntax::select(&file);
+        self.mtime = None;
+        self.filename = Some(file);
+        self.row_contents.iter_mut().for_each(|row| row.highlight = None);
+    }
+
+    fn set_tab_stop(&mut self, tab_stop: usize) {
+        if self.tab_stop != tab_stop {
+            self.tab_stop = tab_stop;
+            self.row_contents
+                .iter_mut()
+                .for_each(|row| row.update_render(tab_stop));
+        }
+    }
+
+    fn number_of_rows(&self) -> usize {
+        self.row_contents.len()
+    }
+
+    fn char_count(&self) -> usize {
+        self.row_contents.iter().map(|row| row.len() + 1).sum()
+    }
+
+    fn get_row(&self, at: usize) -> Option<&str> {
+        self.row_contents.get(at).map(|row| row.row_content.as_str())
+    }
+
+    fn get_render(&self, at: usize) -> Option<&str> {
+        self.row_contents.get(at).map(|row| row.render.as_str())
+    }
+
+    fn update_highlight(&mut self, at: usize) {
+        let Some(row) = self.row_contents.get_mut(at) else {
+            return;
+        };
+        if row.highlight.is_none() {
+            row.highlight = Some(self.syntax.highlight(&row.render));
+        }
+    }
+
+    fn cell_at(&self, at: usize, render_x: usize) -> Option<(char, HighlightType)> {
+        let render = self.get_render(at).unwrap_or_default();
+        let highlight = self.get_highlight(at);
+        let mut cell_x = 0;
+        for (index, ch) in render.chars().enumerate() {
+            if cell_x == render_x {
+                let highlight = highlight.get(index).copied().unwrap_or(HighlightType::Normal);
+                return Some((ch, highlight));
+            }
+            cell_x += Row::char_width(ch);
+            if cell_x > render_x {
+                return None;
+            }
+        }
+        Some((' ', HighlightType::Normal))
+    }
+
+    fn list_render(&self, at: usize, listchars: &ListChars) -> Option<(String, Vec<HighlightType>)> {
+        self.row_contents
+            .get(at)
+            .map(|row| row.list_render(self.tab_stop, listchars))
+    }
+
+    fn get_highlight(&self, at: usize) -> &[HighlightType] {
+        self.row_contents
+            .get(at)
+            .and_then(|row| row.highlight.as_deref())
+            .unwrap_or_default()
+    }
+
+    fn cx_to_rx(&self, row: usize, cursor_x: usize) -> usize {
+        self.row_contents.get(row).map_or(0, |row| row.cx_to_rx(cursor_x, self.tab_stop))
+    }
+
+    fn rx_to_cx(&self, row: usize, render_x: usize) -> usize {
+        self.row_contents.get(row).map_or(0, |row| row.rx_to_cx(render_x, self.tab_stop))
+    }
+
+    fn row_len(&self, at: usize) -> usize {
+        self.row_contents.get(at).map_or(0, Row::len)
+    }
+
+    fn slice(&self, row: usize, from: usize, to: usize) -> &str {
+        self.row_contents
+            .get(row)
+            .map_or("", |row| row.slice(from, to))
+    }
+
+    fn next_word_start(&self, mut pos: (usize, usize)) -> Option<(usize, usize)> {
+        let start_class = self.char_class_at(pos);
+        let mut seen_whitespace = false;
+        loop {
+            pos = self.next_position(pos)?;
+            if self.row_len(pos.0) == 0 {
+                return Some(pos);
+            }
+            let class = self.char_class_at(pos);
+            if class == CharClass::Whitespace {
+                seen_whitespace = true;
+            } else if class != start_class || seen_whitespace {
+                return Some(pos);
+            }
+        }
+    }
+
+    fn text_object(
+        &self,
+        (row, at): (usize, usize),
+        object: char
+    ) -> Option<((usize, usize), (usize, usize))> {
+        let chars: Vec<char> = self.get_row(row)?.chars().collect();
+        match object {
+            'w' if at < chars.len() => {
+                let class = CharClass::of(chars[at]);
+                let start = chars[..at]
+                    .iter()
+                    .rposition(|&ch| CharClass::of(ch) != class)
+                    .map_or(0, |index| index + 1);
+                Some(((row, start), (row, self.run_end((row, at)))))
+            }
+            '"' | '\'' | '`' => {
+                let quotes: Vec<usize> = (0..chars.len())
+                    .filter(|&index| chars[index] == object)
+                    .filter(|&index| index == 0 || chars[index - 1] != '\\')
+                    .collect();
+                let (open, close) = quotes
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0], pair[1]))
+                    .find(|&(_, close)| at <= close)?;
+                Some(((row, open + 1), (row, close)))
+            }
+            _ => None
+        }
+    }
+
+    fn is_blank(&self, row: usize) -> bool {
+        self.get_row(row).is_some_and(|row| row.trim().is_empty())
+    }
+
+    fn next_paragraph(&self, row: usize) -> (usize, usize) {
+        let last_row = self.number_of_rows().saturating_sub(1);
+        (row..=last_row)
+            .skip_while(|&row| self.is_blank(row))
+            .find(|&row| self.is_blank(row))
+            .map_or((last_row, self.row_len(last_row).saturating_sub(1)), |row| (row, 0))
+    }
+
+    fn previous_paragraph(&self, row: usize) -> (usize, usize) {
+        (0..=row)
+            .rev()
+            .skip_while(|&row| self.is_blank(row))
+            .find(|&row| self.is_blank(row))
+            .map_or((0, 0), |row| (row, 0))
+    }
+
+    fn next_sentence(&self, mut pos: (usize, usize)) -> (usize, usize) {
+        while let Some(next) = self.next_position(pos) {
+            pos = next;
+            if self.is_sentence_start(pos) {
+                return pos;
+            }
+        }
+        let last_row = self.number_of_rows().saturating_sub(1);
+        (last_row, self.row_len(last_row).saturating_sub(1))
+    }
+
+    fn previous_sentence(&self, mut pos: (usize, usize)) -> (usize, usize) {
+        while let Some(previous) = self.previous_position(pos) {
+            pos = previous;
+            if self.is_sentence_start(pos) {
+                return pos;
+            }
+        }
+        (0, 0)
+    }
+
+    fn is_sentence_start(&self, (row, at): (usize, usize)) -> bool {
+        if self.row_len(row) == 0 {
+            return at == 0;
+        }
+        if self.char_class_at((row, at)) == CharClass::Whitespace {
+            return false;
+        }
+        let mut pos = (row, at);
+        let mut separated = false;
+        loop {
+            let Some(previous) = self.previous_position(pos) else {
+                return true;
+            };
+            pos = previous;
+            if self.row_len(pos.0) == 0 {
+                return true;
+            }
+            if self.char_class_at(pos) != CharClass::Whitespace {
+                break;
+            }
+            separated = true;
+        }
+        let before: Vec<char> =
+            self.get_row(pos.0).unwrap_or_default().chars().take(pos.1 + 1).collect();
+        separated
+            && before
+                .iter()
+                .rev()
+                .find(|&&ch| !")]\"'".contains(ch))
+                .is_some_and(|&ch| ".!?".contains(ch))
+    }
+
+    fn run_end(&self, (row, at): (usize, usize)) -> usize {
+        let class = self.char_class_at((row, at));
+        let mut end = at;
+        while end < self.row_len(row) && self.char_class_at((row, end)) == class {
+            end += 1;
+        }
+        end
+    }
+
+    fn closed_fold(&self, row: usize) -> Option<&Range<usize>> {
+        self.folds.iter().find(|fold| fold.contains(&row))
+    }
+
+    fn visible_row(&self, row: usize) -> usize {
+        self.closed_fold(row).map_or(row, |fold| fold.start)
+    }
+
+    fn next_visible_row(&self, row: usize) -> usize {
+        self.closed_fold(row).map_or(row + 1, |fold| fold.end)
+    }
+
+    fn previous_visible_row(&self, row: usize) -> usize {
+        self.visible_row(row.saturating_sub(1))
+    }
+
+    fn fold_range(&self, row: usize) -> Option<Range<usize>> {
+        let is_blank = |row: usize| self.get_row(row).unwrap_or_default().trim().is_empty();
+        let indent = |row: usize| self.cx_to_rx(row, self.indentation(row).chars().count());
+        if row >= self.number_of_rows() || is_blank(row) {
+            return None;
+        }
+        let mut last = row;
+        for next in row + 1..self.number_of_rows() {
+            if is_blank(next) {
+                continue;
+            }
+            if indent(next) <= indent(row) {
+                break;
+            }
+            last = next;
+        }
+        (last > row).then_some(row..last + 1)
+    }
+
+    fn toggle_fold(&mut self, row: usize) -> bool {
+        if let Some(index) = self.folds.iter().position(|fold| fold.start == row) {
+            self.folds.remove(index);
+            return true;
+        }
+        let Some(range) = self.fold_range(row) else {
+            return false;
+        };
+        self.folds
+            .retain(|fold| fold.start < range.start || fold.end > range.end);
+        self.folds.push(range);
+        true
+    }
+
+    fn update_folds(&mut self, edit: &Edit) {
+        let (first, last, delta) = match *edit {
+            Edit::InsertText { row, .. } | Edit::DeleteText { row, .. } => (row, row, 0),
+            Edit::InsertRow { at, .. } => (at, at, 1),
+            Edit::DeleteRow { at, .. } => (at, at, -1),
+            Edit::SplitRow { row, .. } => (row, row, 1),
+            Edit::JoinRows { row, .. } => (row, row + 1, -1)
+        };
+        self.folds.retain(|fold| last < fold.start || first >= fold.end);
+        for fold in self.folds.iter_mut().filter(|fold| fold.start > last) {
+            *fold = fold.start.saturating_add_signed(delta)..fold.end.saturating_add_signed(delta);
+        }
+    }
+
+    fn first_non_blank(&self, at: usize) -> usize {
+        self.indentation(at).chars().count()
+    }
+
+    fn indentation(&self, at: usize) -> &str {
+        let row_content = self.get_row(at).unwrap_or_default();
+        let end = row_content
+            .find(|ch: char| ch != ' ' && ch != '\t')
+            .unwrap_or(row_content.len());
+        &row_content[..end]
+    }
+
+    fn word_at(&self, (row, at): (usize, usize)) -> Option<String> {
+        let chars: Vec<char> = self.get_row(row)?.chars().collect();
+        let is_word = |ch: &char| CharClass::of(*ch) == CharClass::Word;
+        let start = at + chars.get(at..)?.iter().position(is_word)?;
+        let start = chars[..start]
+            .iter()
+            .rposition(|ch| !is_word(ch))
+            .map_or(0, |index| index + 1);
+        let end = chars[start..]
+            .iter()
+            .position(|ch| !is_word(ch))
+            .map_or(chars.len(), |len| start + len);
+        Some(chars[start..end].iter().collect())
+    }
+
+    fn char_class_at(&self, (row, at): (usize, usize)) -> CharClass {
+        self.get_row(row)
+            .and_then(|row| row.chars().nth(at))
+            .map_or(CharClass::Whitespace, CharClass::of)
+    }
+
+    fn bracket_partner(&self, (row, at): (usize, usize), rows: Range<usize>) -> Option<(usize, usize)> {
+        let ch = self.get_row(row)?.chars().nth(at)?;
+        "()[]{}".contains(ch).then(|| self.matching_bracket((row, at), rows))?
+    }
+
+    fn matching_bracket(&self, (row, at): (usize, usize), rows: Range<usize>) -> Option<(usize, usize)> {
+        let (offset, bracket) = self
+            .get_row(row)?
+            .chars()
+            .enumerate()
+            .skip(at)
+            .find(|&(_, ch)| "()[]{}".contains(ch))?;
+        let start = (row, self.row_contents[row].byte_index(offset));
+        let (partner, forward) = match bracket {
+            '(' => (')', true),
+            '[' => (']', true),
+            '{' => ('}', true),
+            ')' => ('(', false),
+            ']' => ('[', false),
+            _ => ('{', false)
+        };
+        let (row, index) = match forward {
+            true => balance(
+                (row..cmp::min(rows.end, self.number_of_rows()))
+                    .flat_map(|row| self.row_chars(row))
+                    .skip_while(|&(position, _)| position < start),
+                bracket,
+                partner
+            ),
+            false => balance(
+                (rows.start..=row)
+                    .rev()
+                    .flat_map(|row| self.row_chars(row).rev())
+                    .skip_while(|&(position, _)| position > start),
+                bracket,
+                partner
+            )
+        }?;
+        Some((row, self.row_contents[row].char_index(index)))
+    }
+
+    fn row_chars(&self, row: usize) -> impl DoubleEndedIterator<Item = ((usize, usize), char)> + '_ {
+        self.get_row(row)
+            .unwrap_or_default()
+            .char_indices()
+            .map(move |(index, ch)| ((row, index), ch))
+    }
+
+    fn next_position(&self, (row, at): (usize, usize)) -> Option<(usize, usize)> {
+        if at < self.row_len(row) {
+            Some((row, at + 1))
+        } else if row + 1 < self.number_of_rows() {
+            Some((row + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    fn previous_position(&self, (row, at): (usize, usize)) -> Option<(usize, usize)> {
+        if at > 0 {
+            Some((row, at - 1))
+        } else if row > 0 {
+            Some((row - 1, self.row_len(row - 1)))
+        } else {
+            None
+        }
+    }
+
+    fn find(
+        &self,
+        pattern: &Pattern,
+        (row, at): (usize, usize),
+        direction: SearchDirection
+    ) -> Option<((usize, usize), usize)> {
+        let number_of_rows = self.number_of_rows();
+        let current_row = self.row_contents.get(row)?;
+        let row_content = current_row.row_content.as_str();
+        let first_match = match direction {
+            SearchDirection::Forward => {
+                pattern.find_from(row_content, current_row.byte_index(at + 1))
+            }
+            SearchDirection::Backward => {
+                pattern.rfind_before(row_content, current_row.byte_index(at))
+            }
+        };
+        if let Some(range) = first_match {
+            return Some(current_row.char_range(row, range));
+        }
+        (1..=number_of_rows).find_map(|i| {
+            let current = match direction {
+                SearchDirection::Forward => (row + i) % number_of_rows,
+                SearchDirection::Backward => (row + number_of_rows - i) % number_of_rows
+            };
+            let row = self.row_contents.get(current)?;
+            match direction {
+                SearchDirection::Forward => pattern.find_from(&row.row_content, 0),
+                SearchDirection::Backward => {
+                    pattern.rfind_before(&row.row_content, row.row_content.len())
+                }
+            }
+            .map(|range| row.char_range(current, range))
+        })
+    }
+
+    fn apply(&mut self, edit: &Edit) {
+        self.update_folds(edit);
+        match edit {
+            Edit::InsertText { row, at, text } => {
+                let row = &mut self.row_contents[*row];
+                let at = row.byte_index(*at);
+                row.row_content.insert_str(at, text);
+                row.update_render(self.tab_stop);
+            }
+            Edit::DeleteText { row, at, text } => {
+                let row = &mut self.row_contents[*row];
+                let at = row.byte_index(*at);
+                row.row_content.replace_range(at..at + text.len(), "");
+                row.update_render(self.tab_stop);
+            }
+            Edit::InsertRow { at, contents } => {
+                self.row_contents.insert(*at, Row::new(contents.clone(), self.tab_stop));
+            }
+            Edit::DeleteRow { at, .. } => {
+                self.row_contents.remove(*at);
+            }
+            Edit::SplitRow { row, at } => {
+                let current_row = &mut self.row_contents[*row];
+                let at = current_row.byte_index(*at);
+                let new_row = current_row.row_content.split_off(at);
+                current_row.update_render(self.tab_stop);
+                self.row_contents.insert(row + 1, Row::new(new_row, self.tab_stop));
+            }
+            Edit::JoinRows { row, .. } => {
+                let next_row = self.row_contents.remove(row + 1);
+                let current_row = &mut self.row_contents[*row];
+                current_row.row_content.push_str(&next_row.row_content);
+                current_row.update_render(self.tab_stop);
+            }
+        }
+    }
+
+    fn edit(&mut self, edit: Edit) {
+        self.apply(&edit);
+        self.pending_edits.push(edit);
+        self.dirty += 1;
+    }
+
+    fn discard_edits(&mut self) {
+        let edits = mem::take(&mut self.pending_edits);
+        edits.iter().rev().for_each(|edit| self.apply(&edit.inverse()));
+        self.dirty -= edits.len();
+    }
+
+    fn commit_edits(&mut self, cursor_before: (usize, usize), cursor_after: (usize, usize)) {
+        if !self.pending_edits.is_empty() {
+            self.undo_stack.push(UndoGroup {
+                edits: mem::take(&mut self.pending_edits),
+                cursor_before,
+                cursor_after
+            });
+            self.redo_stack.clear();
+        }
+    }
+
+    fn undo(&mut self) -> Option<(usize, usize)> {
+        let group = self.undo_stack.pop()?;
+        group
+            .edits
+            .iter()
+            .rev()
+            .for_each(|edit| self.apply(&edit.inverse()));
+        self.dirty += 1;
+        let cursor = group.cursor_before;
+        self.redo_stack.push(group);
+        Some(cursor)
+    }
+
+    fn redo(&mut self) -> Option<(usize, usize)> {
+        let group = self.redo_stack.pop()?;
+        group.edits.iter().for_each(|edit| self.apply(edit));
+        self.dirty += 1;
+        let cursor = group.cursor_after;
+        self.undo_stack.push(group);
+        Some(cursor)
+    }
+
+    fn insert_char(&mut self, row: usize, at: usize, ch: char) {
+        if row == self.number_of_rows() {
+            self.edit(Edit::InsertRow {
+                at: row,
+                contents: String::new()
+            });
+        }
+        let at = cmp::min(at, self.row_len(row));
+        self.edit(Edit::InsertText {
+            row,
+            at,
+            text: ch.to_string()
+        });
+    }
+
+    fn insert_newline(&mut self, row: usize, at: usize) {
+        if row == self.number_of_rows() {
+            self.edit(Edit::InsertRow {
+                at: row,
+                contents: String::new()
+            });
+        }
+        let at = cmp::min(at, self.row_len(row));
+        self.edit(Edit::SplitRow { row, at });
+    }
+
+    fn contents(&self) -> String {
+        self.row_contents
+            .iter()
+            .map(|row| row.row_content.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    fn file_contents(&self, config: &Config) -> String {
+        let mut lines: Vec<&str> = self
+            .row_contents
+            .iter()
+            .map(|row| match config.trim_trailing_whitespace {
+                true => row.row_content.trim_end_matches([' ', '\t']),
+                false => row.row_content.as_str()
+            })
+            .collect();
+        if config.final_newline {
+            while lines.last() == Some(&"") {
+                lines.pop();
+            }
+        }
+        let line_ending = self.file_format.line_ending();
+        let mut contents = String::from(if self.bom { "\u{feff}" } else { "" });
+        contents.push_str(&lines.join(line_ending));
+        if (config.final_newline || self.eol) && !lines.is_empty() {
+            contents.push_str(line_ending);
+        }
+        contents
+    }
+
+    fn save(&mut self, config: &Config) -> io::Result<usize> {
+        match &self.filename {
+            None => Err(io::Error::other("no file name")),
+            Some(name) => {
+                let contents = self.file_contents(config);
+                fs::write(name, &contents)?;
+                self.mtime = modified_time(name);
+                if self.swapped_at > 0 || self.swap_found {
+                    let _ = swap::remove(name);
+                }
+                self.dirty = 0;
+                self.swapped_at = 0;
+                self.swap_found = false;
+                Ok(contents.len())
+            }
+        }
+    }
+
+    fn disk_change(&self) -> DiskChange {
+        match (&self.filename, self.mtime) {
+            (Some(file), Some(loaded)) => match modified_time(file) {
+                None => DiskChange::Deleted,
+                Some(current) if current != loaded => DiskChange::Modified,
+                Some(_) => DiskChange::Unchanged
+            },
+            _ => DiskChange::Unchanged
+        }
+    }
+
+    fn write_swap(&mut self) -> io::Result<()> {
+        match &self.filename {
+            Some(file) if self.dirty > 0 && self.dirty != self.swapped_at && !self.swap_found => {
+                self.swapped_at = self.dirty;
+                swap::write(file, &self.contents())
+            }
+            _ => Ok(())
+        }
+    }
+
+    fn remove_swap(&mut self) -> io::Result<()> {
+        match &self.filename {
+            Some(file) if self.swapped_at > 0 => {
+                self.swapped_at = 0;
+                swap::remove(file)
+            }
+            _ => Ok(())
+        }
+    }
+
+    fn recover(&mut self) -> io::Result<()> {
+        let file = self
+            .filename
+            .as_deref()
+            .ok_or_else(|| io::Error::other("no file name"))?;
+        let contents = swap::read(file)?;
+        self.row_contents = contents
+            .lines()
+            .map(|line| Row::new(line.into(), self.tab_stop))
+            .collect();
+        self.pending_edits.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.folds.clear();
+        self.dirty += 1;
+        self.swapped_at = self.dirty;
+        self.swap_found = false;
+        Ok(())
+    }
+
+    fn delete_char(&mut self, row: usize, at: usize) {
+        let ch = self.get_row(row).and_then(|row| row.chars().nth(at));
+        if let Some(ch) = ch {
+            self.edit(Edit::DeleteText {
+                row,
+                at,
+                text: ch.to_string()
+            });
+        }
+    }
+
+    fn insert_row(&mut self, at: usize, contents: String) {
+        if self.row_contents.is_empty() {
+            self.edit(Edit::InsertRow {
+                at: 0,
+                contents: String::new()
+            });
+        }
+        let at = cmp::min(at, self.number_of_rows());
+        self.edit(Edit::InsertRow { at, contents });
+    }
+
+    fn insert_text(&mut self, row: usize, at: usize, text: &str) -> (usize, usize) {
+        if row == self.number_of_rows() {
+            self.edit(Edit::InsertRow {
+                at: row,
+                contents: String::new()
+            });
+        }
+        let (mut row, mut at) = (row, cmp::min(at, self.row_len(row)));
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.edit(Edit::SplitRow { row, at });
+                row += 1;
+                at = 0;
+            }
+            if !line.is_empty() {
+                self.edit(Edit::InsertText {
+                    row,
+                    at,
+                    text: line.into()
+                });
+                at += line.chars().count();
+            }
+        }
+        (row, at)
+    }
+
+    fn range_end(&self, end: (usize, usize)) -> (usize, usize) {
+        self.next_position(end)
+            .unwrap_or((end.0, self.row_len(end.0)))
+    }
+
+    fn text_range(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        (start.0..=end.0)
+            .map(|row| {
+                let from = if row == start.0 { start.1 } else { 0 };
+                let to = if row == end.0 { end.1 } else { self.row_len(row) };
+                self.slice(row, from, to)
+            })
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    fn substitute(&mut self, row: usize, pattern: &Pattern, replacement: &str, global: bool) -> usize {
+        let Some(current_row) = self.row_contents.get(row) else {
+            return 0;
+        };
+        let content = current_row.row_content.as_str();
+        let replacements: Vec<(usize, String, String)> = pattern
+            .replacements(content, replacement, global)
+            .into_iter()
+            .map(|(range, text)| {
+                (current_row.char_index(range.start), content[range].to_string(), text)
+            })
+            .collect();
+        for (at, matched, text) in replacements.iter().rev() {
+            if !matched.is_empty() {
+                self.edit(Edit::DeleteText {
+                    row,
+                    at: *at,
+                    text: matched.clone()
+                });
+            }
+            if !text.is_empty() {
+                self.edit(Edit::InsertText {
+                    row,
+                    at: *at,
+                    text: text.clone()
+                });
+            }
+        }
+        replacements.len()
+    }
+
+    fn delete_range(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        let text = self.text_range(start, end);
+        if start.0 == end.0 {
+            if start.1 < end.1 {
+                self.edit(Edit::DeleteText {
+                    row: start.0,
+                    at: start.1,
+                    text: text.clone()
+                });
+            }
+            return text;
+        }
+        let tail = self.slice(start.0, start.1, self.row_len(start.0)).to_string();
+        if !tail.is_empty() {
+            self.edit(Edit::DeleteText {
+                row: start.0,
+                at: start.1,
+                text: tail
+            });
+        }
+        for _ in start.0 + 1..end.0 {
+            let contents = self.get_row(start.0 + 1).unwrap_or_default().to_string();
+            self.edit(Edit::DeleteRow {
+                at: start.0 + 1,
+                contents
+            });
+        }
+        let head = self.slice(start.0 + 1, 0, end.1).to_string();
+        if !head.is_empty() {
+            self.edit(Edit::DeleteText {
+                row: start.0 + 1,
+                at: 0,
+                text: head
+            });
+        }
+        self.edit(Edit::JoinRows {
+            row: start.0,
+            at: start.1
+        });
+        text
+    }
+
+    fn delete_row(&mut self, at: usize) {
+        let contents = match self.get_row(at) {
+            Some(contents) => contents.to_string(),
+            None => return
+        };
+        self.edit(Edit::DeleteRow { at, contents });
+        if self.row_contents.is_empty() {
+            self.edit(Edit::InsertRow {
+                at: 0,
+                contents: String::new()
+            });
+        }
+    }
+
+    fn join_adjacent_rows(&mut self, at: usize) {
+        if at + 1 < self.number_of_rows() {
+            self.edit(Edit::JoinRows {
+                row: at,
+                at: self.row_len(at)
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Edit {
+    InsertText { row: usize, at: usize, text: String },
+    DeleteText { row: usize, at: usize, text: String },
+    InsertRow { at: usize, contents: String },
+    DeleteRow { at: usize, contents: String },
+    SplitRow { row: usize, at: usize },
+    JoinRows { row: usize, at: usize }
+}
+
+impl Edit {
+    fn inverse(&self) -> Self {
+        match self.clone() {
+            Edit::InsertText { row, at, text } => Edit::DeleteText { row, at, text },
+            Edit::DeleteText { row, at, text } => Edit::InsertText { row, at, text },
+            Edit::InsertRow { at, contents } => Edit::DeleteRow { at, contents },
+            Edit::DeleteRow { at, contents } => Edit::InsertRow { at, contents },
+            Edit::SplitRow { row, at } => Edit::JoinRows { row, at },
+            Edit::JoinRows { row, at } => Edit::SplitRow { row, at }
+        }
+    }
+}
+
+struct UndoGroup {
+    edits: Vec<Edit>,
+    cursor_before: (usize, usize),
+    cursor_after: (usize, usize)
+}
+
+/// Where `Reader` takes its events from. The editor reads the terminal, but
+/// any other source of events can stand in for it.
+trait EventSource {
+    fn next_event(&mut self, timeout: Duration) -> crossterm::Result<Option<Event>>;
+}
+
+struct TerminalEvents;
+
+impl EventSource for TerminalEvents {
+    fn next_event(&mut self, timeout: Duration) -> crossterm::Result<Option<Event>> {
+        if event::poll(timeout)? {
+            return event::read().map(Some);
+        }
+        Ok(None)
+    }
+}
+
+struct Reader {
+    source: RefCell<Box<dyn EventSource>>,
+    queued: RefCell<VecDeque<KeyEvent>>,
+    recording: RefCell<Option<Vec<KeyEvent>>>
+}
+
+impl Reader {
+    fn new() -> Self {
+        Self::with_source(Box::new(TerminalEvents))
+    }
+
+    fn with_source(source: Box<dyn EventSource>) -> Self {
+        Self {
+            source: RefCell::new(source),
+            queued: RefCell::new(VecDeque::new()),
+            recording: RefCell::new(None)
+        }
+    }
+
+    /// Returns queued keys first, then waits up to 100ms for an event and
+    /// returns `None` when nothing arrived, so the caller gets a chance to
+    /// redraw between keys. Every key handed out is added to the recording.
+    fn read_event(&self) -> crossterm::Result<Option<Event>> {
+        let event = match self.next_queued() {
+            Some(key) => Some(Event::Key(key)),
+            None => self.source.borrow_mut().next_event(Duration::from_millis(100))?
+        };
+        if let (Some(Event::Key(key)), Some(recording)) =
+            (&event, self.recording.borrow_mut().as_mut())
+        {
+            recording.push(*key);
+        }
+        Ok(event)
+    }
+
+    fn queue(&self, keys: &[KeyEvent]) {
+        let mut queued = self.queued.borrow_mut();
+        keys.iter().rev().for_each(|&key| queued.push_front(key));
+    }
+
+    fn next_queued(&self) -> Option<KeyEvent> {
+        self.queued.borrow_mut().pop_front()
+    }
+
+    fn has_queued(&self) -> bool {
+        !self.queued.borrow().is_empty()
+    }
+
+    fn clear_queue(&self) {
+        self.queued.borrow_mut().clear();
+    }
+
+    fn start_recording(&self) {
+        *self.recording.borrow_mut() = Some(Vec::new());
+    }
+
+    fn stop_recording(&self) -> Vec<KeyEvent> {
+        self.recording.borrow_mut().take().unwrap_or_default()
+    }
+}
+
+fn truncate_chars(string: &str, max: usize) -> &str {
+    string
+        .char_indices()
+        .nth(max)
+        .map_or(string, |(index, _)| &string[..index])
+}
+
+type Segment = ((u16, u16), usize);
+
+fn segment_at<'a>(content: &'a str, segments: &[Segment], index: usize) -> Option<((u16, u16), &'a str)> {
+    let &(position, start) = segments.get(index)?;
+    let end = segments.get(index + 1).map_or(content.len(), |&(_, end)| end);
+    Some((position, &content[start..end]))
+}
+
+struct EditorContents {
+    content: String,
+    segments: Vec<Segment>,
+    previous_content: String,
+    previous_segments: Vec<Segment>,
+    redrawn_from: Vec<u16>,
+    cursor: Option<(u16, u16)>,
+    output: String,
+    writer: Box<dyn Write>
+}
+
+impl EditorContents {
+    fn new() -> Self {
+        Self::with_writer(Box::new(stdout()))
+    }
+
+    fn with_writer(writer: Box<dyn Write>) -> Self {
+        Self {
+            content: String::new(),
+            segments: Vec::new(),
+            previous_content: String::new(),
+            previous_segments: Vec::new(),
+            redrawn_from: Vec::new(),
+            cursor: None,
+            output: String::new(),
+            writer
+        }
+    }
+
+    fn reserve_frame(&mut self, (columns, rows): (usize, usize)) {
+        self.content.reserve((columns * 4 + 32) * (rows + 2));
+        self.output.reserve((columns * 4 + 32) * (rows + 2));
+    }
+
+    fn move_to(&mut self, x: usize, y: usize) {
+        self.segments.push(((x as u16, y as u16), self.content.len()));
+    }
+
+    fn set_cursor(&mut self, x: usize, y: usize) {
+        self.cursor = Some((x as u16, y as u16));
+    }
+
+    fn invalidate(&mut self) {
+        self.previous_segments.clear();
+    }
+
+    fn write_changes(&mut self) {
+        self.output.clear();
+        self.redrawn_from.clear();
+        let same_layout = self
+            .segments
+            .iter()
+            .map(|&(position, _)| position)
+            .eq(self.previous_segments.iter().map(|&(position, _)| position));
+        let mut hidden = false;
+        for index in 0..self.segments.len() {
+            let Some(((x, y), segment)) = segment_at(&self.content, &self.segments, index) else {
+                break;
+            };
+            let row = y as usize;
+            if self.redrawn_from.len() <= row {
+                self.redrawn_from.resize(row + 1, u16::MAX);
+            }
+            let previous = segment_at(&self.previous_content, &self.previous_segments, index);
+            if same_layout && self.redrawn_from[row] > x && previous == Some(((x, y), segment)) {
+                continue;
+            }
+            self.redrawn_from[row] = cmp::min(self.redrawn_from[row], x);
+            if !hidden {
+                cursor::Hide.write_ansi(&mut self.output).unwrap();
+                hidden = true;
+            }
+            cursor::MoveTo(x, y).write_ansi(&mut self.output).unwrap();
+            self.output.push_str(segment);
+        }
+        match self.cursor.take() {
+            Some((x, y)) => {
+                cursor::MoveTo(x, y).write_ansi(&mut self.output).unwrap();
+                cursor::Show.write_ansi(&mut self.output).unwrap();
+            }
+            None => cursor::Hide.write_ansi(&mut self.output).unwrap()
+        }
+        mem::swap(&mut self.content, &mut self.previous_content);
+        mem::swap(&mut self.segments, &mut self.previous_segments);
+        self.content.clear();
+        self.segments.clear();
+    }
+
+    fn push(&mut self, ch: char) {
+        self.content.push(ch);
+    }
+
+    fn push_str(&mut self, string: &str) {
+        self.content.push_str(string);
+    }
+
+    fn push_spaces(&mut self, count: usize) {
+        self.content.extend(iter::repeat_n(' ', count));
+    }
+}
+
+impl io::Write for EditorContents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(s) => {
+                self.content.push_str(s);
+                Ok(s.len())
+            }
+            Err(_) => Err(io::ErrorKind::WriteZero.into())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_changes();
+        let out = self.writer.write_all(self.output.as_bytes());
+        self.writer.flush()?;
+        out
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    VisualBlock
+}
+
+impl Mode {
+    fn label(&self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "VISUAL LINE",
+            Mode::VisualBlock => "VISUAL BLOCK"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Quit,
+    ForceQuit,
+    Write,
+    WriteQuit,
+    Goto(usize),
+    Edit(Option<PathBuf>),
+    View(Option<PathBuf>),
+    Set(String),
+    Substitute {
+        range: LineRange,
+        pattern: String,
+        replacement: String,
+        global: bool
+    },
+    BufferNext,
+    BufferPrevious,
+    ListBuffers,
+    FileInfo,
+    Recover,
+    Split(Split),
+    Close,
+    Find(Option<String>),
+    Help,
+    Reload
+}
+
+impl Command {
+    fn parse(input: &str) -> Option<Self> {
+        let (range, rest) = LineRange::parse(input.trim());
+        if let Some(command) = Self::parse_substitute(rest, range) {
+            return Some(command);
+        }
+        let mut parts = input.trim().splitn(2, char::is_whitespace);
+        let name = parts.next()?;
+        let argument = parts.next().map(str::trim).filter(|arg| !arg.is_empty());
+        match (name, argument) {
+            ("q" | "quit", None) => Some(Command::Quit),
+            ("q!" | "quit!", None) => Some(Command::ForceQuit),
+            ("w" | "write", None) => Some(Command::Write),
+            ("wq" | "x", None) => Some(Command::WriteQuit),
+            (line, None) if line.bytes().all(|byte| byte.is_ascii_digit()) => {
+                line.parse().ok().map(Command::Goto)
+            }
+            ("e" | "edit", path) => Some(Command::Edit(path.map(PathBuf::from))),
+            ("e!" | "edit!", None) => Some(Command::Reload),
+            ("e!" | "edit!", Some(path)) => Some(Command::Edit(Some(path.into()))),
+            ("vie" | "view", path) => Some(Command::View(path.map(PathBuf::from))),
+            ("set" | "se", Some(option)) => Some(Command::Set(option.into())),
+            ("bn" | "bnext", None) => Some(Command::BufferNext),
+            ("bp" | "bprevious" | "bN" | "bNext", None) => Some(Command::BufferPrevious),
+            ("ls" | "buffers" | "files", None) => Some(Command::ListBuffers),
+            ("f" | "file" | "info", None) => Some(Command::FileInfo),
+            ("rec" | "recover", None) => Some(Command::Recover),
+            ("h" | "help", None) => Some(Command::Help),
+            ("sp" | "split", None) => Some(Command::Split(Split::Horizontal)),
+            ("vs" | "vsplit", None) => Some(Command::Split(Split::Vertical)),
+            ("clo" | "close", None) => Some(Command::Close),
+            ("fin" | "find", query) => Some(Command::Find(query.map(String::from))),
+            _ => None
+        }
+    }
+
+    fn parse_substitute(input: &str, range: LineRange) -> Option<Self> {
+        let rest = input
+            .strip_prefix("substitute")
+            .or_else(|| input.strip_prefix('s'))?;
+        let delimiter = rest
+            .chars()
+            .next()
+            .filter(|&ch| !ch.is_alphanumeric() && !ch.is_whitespace() && !"\\\"|".contains(ch))?;
+        let mut parts = vec![String::new()];
+        let mut chars = rest[delimiter.len_utf8()..].chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' if chars.peek() == Some(&delimiter) => {
+                    parts.last_mut()?.push(delimiter);
+                    chars.next();
+                }
+                _ if ch == delimiter && parts.len() < 3 => parts.push(String::new()),
+                _ => parts.last_mut()?.push(ch)
+            }
+        }
+        let flags = parts.get(2).map_or("", String::as_str);
+        if !flags.chars().all(|flag| flag == 'g') {
+            return None;
+        }
+        Some(Command::Substitute {
+            range,
+            global: !flags.is_empty(),
+            replacement: parts.get(1).cloned().unwrap_or_default(),
+            pattern: parts.swap_remove(0)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LineRange {
+    Current,
+    Whole,
+    Lines(usize, usize)
+}
+
+impl LineRange {
+    fn parse(input: &str) -> (Self, &str) {
+        if let Some(rest) = input.strip_prefix('%') {
+            return (LineRange::Whole, rest);
+        }
+        match Self::parse_line(input) {
+            Some((first, rest)) => match rest.strip_prefix(',').and_then(Self::parse_line) {
+                Some((last, rest)) => (LineRange::Lines(first, last), rest),
+                None => (LineRange::Lines(first, first), rest)
+            },
+            None => (LineRange::Current, input)
+        }
+    }
+
+    fn parse_line(input: &str) -> Option<(usize, &str)> {
+        let end = input
+            .find(|ch: char| !ch.is_ascii_digit())
+            .unwrap_or(input.len());
+        input[..end].parse().ok().map(|line| (line, &input[end..]))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Register {
+    contents: Vec<String>,
+    linewise: bool
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BlockInsert {
+    rows: Range<usize>,
+    column: usize,
+    start: (usize, usize),
+    append: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Comment,
+    Indent,
+    Dedent,
+    Upper,
+    Lower
+}
+
+impl Operator {
+    fn from_key(key: char) -> Option<Self> {
+        match key {
+            'd' => Some(Operator::Delete),
+            'c' => Some(Operator::Change),
+            'y' => Some(Operator::Yank),
+            '>' => Some(Operator::Indent),
+            '<' => Some(Operator::Dedent),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+    Toggle
+}
+
+impl Case {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Case::Upper => text.to_uppercase(),
+            Case::Lower => text.to_lowercase(),
+            Case::Toggle => text
+                .chars()
+                .flat_map(|ch| match ch.is_lowercase() {
+                    true => ch.to_uppercase().collect::<Vec<char>>(),
+                    false => ch.to_lowercase().collect()
+                })
+                .collect()
+        }
+    }
+}
+
+struct Editor {
+    reader: Reader,
+    output: Output,
+    mode: Mode,
+    quit_times: u8,
+    pending_key: Option<char>,
+    pending_operator: Option<Operator>,
+    count: Option<usize>,
+    change_start: Option<(usize, usize)>,
+    block_insert: Option<BlockInsert>,
+    register: Register,
+    recording: Vec<KeyEvent>,
+    last_change: Vec<KeyEvent>,
+    replaying: bool,
+    last_input: Instant,
+    autosaved_at: Option<Instant>,
+    macros: HashMap<char, Vec<KeyEvent>>,
+    macro_register: Option<char>,
+    last_macro: Option<char>,
+    macro_depth: usize
+}
+
+impl Editor {
+    fn new(editor_rows: EditorRows, config: Config, config_errors: &[String]) -> Self {
+        let mut output = Output::new(editor_rows, config);
+        if let Some(error) = config_errors.first() {
+            output.set_message(error);
+        }
+        Self::with_output(output)
+    }
+
+    fn with_output(output: Output) -> Self {
+        let quit_times = output.config.quit_times;
+        Self {
+            reader: Reader::new(),
+            output,
+            mode: Mode::Normal,
+            quit_times,
+            pending_key: None,
+            pending_operator: None,
+            count: None,
+            change_start: None,
+            block_insert: None,
+            register: Register::default(),
+            recording: Vec::new(),
+            last_change: Vec::new(),
+            replaying: false,
+            last_input: Instant::now(),
+            autosaved_at: None,
+            macros: HashMap::new(),
+            macro_register: None,
+            last_macro: None,
+            macro_depth: 0
+        }
+    }
+
+    fn process_keypress(&mut self) -> crossterm::Result<bool> {
+        match self.reader.read_event()? {
+            Some(Event::Key(key)) => {
+                self.last_input = Instant::now();
+                let result = self.process_key(key);
+                self.output.needs_redraw = true;
+                result
+            }
+            Some(Event::Resize(columns, rows)) => {
+                self.output.resize(columns, rows);
+                self.output.needs_redraw = true;
+                Ok(true)
+            }
+            Some(Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Down(MouseButton::Left),
+                column,
+                row,
+                ..
+            })) => {
+                self.output.click(column, row, self.mode == Mode::Insert);
+                self.output.needs_redraw = true;
+                Ok(true)
+            }
+            Some(Event::Mouse(MouseEvent {
+                kind: kind @ (MouseEventKind::ScrollUp | MouseEventKind::ScrollDown),
+                ..
+            })) => {
+                self.output.scroll_wheel(kind == MouseEventKind::ScrollDown);
+                self.output.needs_redraw = true;
+                Ok(true)
+            }
+            Some(Event::FocusGained) => {
+                self.output.check_disk_change();
+                Ok(true)
+            }
+            None if self.autosave_due() => {
+                self.autosaved_at = Some(Instant::now());
+                self.output.autosave();
+                Ok(true)
+            }
+            None if self.last_input.elapsed() >= SWAP_DELAY => {
+                self.output.write_swap_files();
+                Ok(true)
+            }
+            _ => Ok(true)
+        }
+    }
+
+    fn autosave_due(&self) -> bool {
+        let interval = Duration::from_secs(self.output.config.autosave);
+        let editor_rows = &self.output.editor_rows;
+        let retry = self
+            .autosaved_at
+            .is_none_or(|at| at < self.last_input || at.elapsed() >= interval);
+        !interval.is_zero()
+            && editor_rows.dirty > 0
+            && editor_rows.filename.is_some()
+            && !editor_rows.read_only
+            && self.last_input.elapsed() >= interval
+            && retry
+    }
+
+    fn process_key(&mut self, key: KeyEvent) -> crossterm::Result<bool> {
+        self.output.cursor_controller.detached = false;
+        if !self.replaying {
+            if self.is_idle() {
+                self.recording.clear();
+            }
+            self.recording.push(key);
+        }
+        let cursor = self.output.cursor_position();
+        let mode = self.mode;
+        let keep_running = self.handle_key(key)?;
+        let editor_rows = &mut self.output.editor_rows;
+        if editor_rows.read_only && !editor_rows.pending_edits.is_empty() {
+            editor_rows.discard_edits();
+            self.output.cursor_controller.cursor_y = cursor.0;
+            self.output.cursor_controller.cursor_x = cursor.1;
+            self.output.set_message(READ_ONLY_MESSAGE);
+        }
+        if self.mode == Mode::Normal {
+            let changed = !self.output.editor_rows.pending_edits.is_empty();
+            let change_start = self.change_start.take().unwrap_or(cursor);
+            let cursor_after = self.output.cursor_position();
+            self.output
+                .editor_rows
+                .commit_edits(change_start, cursor_after);
+            if changed && !self.replaying && self.is_idle() {
+                self.last_change = self.recording.clone();
+            }
+        } else if mode == Mode::Normal {
+            self.change_start = Some(cursor);
+        }
+        Ok(keep_running)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.mode == Mode::Normal
+            && self.pending_key.is_none()
+            && self.pending_operator.is_none()
+            && self.count.is_none()
+    }
+
+    fn execute_macro(&mut self, name: char, count: usize) -> crossterm::Result<bool> {
+        self.last_macro = Some(name);
+        let Some(keys) = self.macros.get(&name).filter(|keys| !keys.is_empty()) else {
+            self.output.set_message(&format!("Register {} is empty", name));
+            return Ok(true);
+        };
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            self.reader.clear_queue();
+            self.output.set_message("E169: Command too recursive");
+            return Ok(true);
+        }
+        self.reader.queue(&keys.repeat(count));
+        self.macro_depth += 1;
+        // A macro played while recording is kept as the `@` keys that ran it.
+        let recording = self.reader.recording.take();
+        let mut keep_running = true;
+        while keep_running && self.reader.has_queued() {
+            if let Some(Event::Key(key)) = self.reader.read_event()? {
+                keep_running = self.process_key(key)?;
+            }
+        }
+        *self.reader.recording.borrow_mut() = recording;
+        self.macro_depth -= 1;
+        Ok(keep_running)
+    }
+
+    fn repeat_last_change(&mut self, count: Option<usize>) -> crossterm::Result<bool> {
+        let mut keys = self.last_change.clone();
+        if let Some(count) = count {
+            let digits = keys
+                .iter()
+                .take_while(|key| matches!(key.code, KeyCode::Char(ch) if ch.is_ascii_digit()))
+                .count();
+            keys.splice(
+                ..digits,
+                count
+                    .to_string()
+                    .chars()
+                    .map(|ch| KeyEvent::new(KeyCode::Char(ch), event::KeyModifiers::NONE))
+            );
+            self.last_change = keys.clone();
+        }
+        self.replaying = true;
+        let mut keep_running = true;
+        for key in keys {
+            keep_running = self.process_key(key)?;
+            if !keep_running {
+                break;
+            }
+        }
+        self.replaying = false;
+        Ok(keep_running)
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> crossterm::Result<bool> {
+        let key = self.output.config.translate(key, self.mode == Mode::Insert);
+        if let KeyEvent {
+            code: KeyCode::Char('q'),
+            modifiers: event::KeyModifiers::CONTROL,
+            ..
+        } = key
+        {
+            if self.output.modified_buffer().is_some() && self.quit_times > 0 {
+                self.output.set_message(&format!(
+                    "WARNING!!! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                    self.quit_times
+                ));
+                self.quit_times -= 1;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        self.quit_times = self.output.config.quit_times;
+        match key {
+            KeyEvent {
+                code: KeyCode::Char('s'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.output.save(&self.reader)?;
+            }
+            KeyEvent {
+                code: direction @ (KeyCode::PageUp | KeyCode::PageDown),
+                ..
+            } => self.output.page(direction),
+            KeyEvent {
+                code: KeyCode::Home,
+                ..
+            } => self.output.smart_home(self.mode == Mode::Insert),
+            KeyEvent {
+                code: KeyCode::End,
+                ..
+            } => self.output.move_to_line_end(self.mode == Mode::Insert),
+            _ => match self.mode {
+                Mode::Normal | Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
+                    return self.process_normal_key(key)
+                }
+                Mode::Insert => self.process_insert_key(key)
+            }
+        }
+        Ok(true)
+    }
+
+    fn process_normal_key(&mut self, key: KeyEvent) -> crossterm::Result<bool> {
+        let val = match key {
+            KeyEvent {
+                code: KeyCode::Char(val),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+                ..
+            } => val,
+            KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                let repeat = self.count.take().unwrap_or(1);
+                (0..repeat).for_each(|_| self.output.redo());
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('^' | '6'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                self.count = None;
+                self.output.switch_to_alternate();
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                self.count = None;
+                self.find_file(None)?;
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char(']' | '5'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                self.count = None;
+                self.jump_to_tag();
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::F(1),
+                ..
+            } => {
+                self.pending_key = None;
+                self.count = None;
+                self.output.show_help();
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('t'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                let repeat = self.count.take().unwrap_or(1);
+                (0..repeat).for_each(|_| self.output.pop_tag());
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('g'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                self.count = None;
+                self.output.show_file_info();
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                let repeat = self.count.take().unwrap_or(1);
+                (0..repeat).for_each(|_| self.output.jump_back());
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Tab,
+                ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('i'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                let repeat = self.count.take().unwrap_or(1);
+                (0..repeat).for_each(|_| self.output.jump_forward());
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.count = None;
+                match self.pending_key.take() {
+                    Some(WINDOW_PREFIX) => self.output.move_to_window('w'),
+                    _ => self.pending_key = Some(WINDOW_PREFIX)
+                }
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char(direction @ ('u' | 'd')),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                self.count = None;
+                self.output.half_page(direction == 'd');
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Char('v'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                self.pending_key = None;
+                self.count = None;
+                match self.mode {
+                    Mode::VisualBlock => self.exit_visual_mode(),
+                    Mode::Visual | Mode::VisualLine => self.mode = Mode::VisualBlock,
+                    Mode::Normal | Mode::Insert => {
+                        self.mode = Mode::VisualBlock;
+                        self.output.visual_anchor = Some(self.output.cursor_position());
+                    }
+                }
+                return Ok(true);
+            }
+            KeyEvent {
+                code: KeyCode::Esc,
+                ..
+            } if matches!(self.mode, Mode::Visual | Mode::VisualLine | Mode::VisualBlock) => {
+                self.exit_visual_mode();
+                return Ok(true);
+            }
+            _ => {
+                self.pending_key = None;
+                self.pending_operator = None;
+                self.count = None;
+                return Ok(true);
+            }
+        };
+        let replacing = self.pending_key == Some('r');
+        if let Some(digit) = val
+            .to_digit(10)
+            .filter(|&d| !replacing && (d > 0 || self.count.is_some()))
+        {
+            self.count = Some(self.count.unwrap_or(0) * 10 + digit as usize);
+            return Ok(true);
+        }
+        let count = self.count.take();
+        let repeat = count.unwrap_or(1);
+        if let Some(operator) = self.pending_operator.take() {
+            match (self.pending_key.take(), val) {
+                (Some('i'), object) => self.apply_text_object(operator, object),
+                (_, 'i') if !matches!(
+                    operator,
+                    Operator::Comment | Operator::Indent | Operator::Dedent
+                ) => {
+                    self.pending_operator = Some(operator);
+                    self.pending_key = Some(val);
+                }
+                _ => self.apply_operator(operator, val, repeat)
+            }
+            return Ok(true);
+        }
+        if let Some(pending) = self.pending_key.take() {
+            match (pending, val) {
+                ('g', 'g') => self
+                    .output
+                    .jump(|output| output.goto_row(count.map_or(0, |line| line - 1))),
+                ('g', 'c')
+                    if matches!(self.mode, Mode::Visual | Mode::VisualLine | Mode::VisualBlock) =>
+                {
+                    if let Some((start, end)) = self.output.selection(self.mode) {
+                        self.output.toggle_comment(start.0..end.0 + 1);
+                    }
+                    self.exit_visual_mode();
+                }
+                ('g', 'c') => {
+                    self.pending_operator = Some(Operator::Comment);
+                    self.count = count;
+                }
+                ('g', 'U' | 'u') if matches!(self.mode, Mode::Normal) => {
+                    self.pending_operator =
+                        Some(if val == 'U' { Operator::Upper } else { Operator::Lower });
+                    self.count = count;
+                }
+                ('r', ch) => self.output.replace_chars(ch, repeat),
+                ('m', name) => self.output.set_mark(name),
+                ('q', name) if name.is_ascii_lowercase() => {
+                    self.macro_register = Some(name);
+                    self.reader.start_recording();
+                    self.output.set_message(&format!("recording @{}", name));
+                }
+                ('@', '@') => match self.last_macro {
+                    Some(name) => return self.execute_macro(name, repeat),
+                    None => self.output.set_message("E748: No previously used register")
+                },
+                ('@', name) if name.is_ascii_lowercase() => return self.execute_macro(name, repeat),
+                ('z', 'a') => self.output.toggle_fold(),
+                ('`', name) => self.output.jump(|output| output.jump_to_mark(name)),
+                (WINDOW_PREFIX, 's' | 'S') => self.output.split_window(Split::Horizontal),
+                (WINDOW_PREFIX, 'v') => self.output.split_window(Split::Vertical),
+                (WINDOW_PREFIX, 'c' | 'q') => self.output.close_window(),
+                (WINDOW_PREFIX, direction) => self.output.move_to_window(direction),
+                _ => {}
+            }
+            return Ok(true);
+        }
+        match val {
+            'h' | 'j' | 'k' | 'l' | 'w' | 'b' | 'e' | '0' | '^' | '$' => {
+                (0..repeat).for_each(|_| self.output.move_cursor(val))
+            }
+            'G' => self.output.jump(|output| match count {
+                Some(line) => output.goto_row(line - 1),
+                None => output.move_cursor(val)
+            }),
+            '%' => self.output.jump(|output| output.jump_to_matching_bracket()),
+            '{' | '}' | '(' | ')' => self
+                .output
+                .jump(|output| (0..repeat).for_each(|_| output.move_cursor(val))),
+            'g' => {
+                self.pending_key = Some(val);
+                self.count = count;
+            }
+            _ if matches!(self.mode, Mode::Visual | Mode::VisualLine | Mode::VisualBlock) => {
+                self.process_visual_command(val)
+            }
+            'd' | 'c' | 'y' | '>' | '<' => {
+                self.pending_operator = Operator::from_key(val);
+                self.count = count;
+            }
+            'r' => {
+                self.pending_key = Some(val);
+                self.count = count;
+            }
+            'm' | '`' | 'z' => self.pending_key = Some(val),
+            'q' => match self.macro_register.take() {
+                Some(name) => {
+                    let mut keys = self.reader.stop_recording();
+                    keys.pop();
+                    self.macros.insert(name, keys);
+                    self.output.set_message("");
+                }
+                None if self.output.editor_rows.scratch_name == Some(HELP_NAME) => {
+                    self.output.switch_to_alternate()
+                }
+                None => self.pending_key = Some(val)
+            },
+            '@' => {
+                self.pending_key = Some(val);
+                self.count = count;
+            }
+            'v' | 'V' => {
+                self.mode = if val == 'v' { Mode::Visual } else { Mode::VisualLine };
+                self.output.visual_anchor = Some(self.output.cursor_position());
+            }
+            'i' => self.mode = Mode::Insert,
+            'x' => self.output.delete_chars(repeat),
+            '~' => self.output.toggle_case(repeat),
+            'J' => self.output.join_lines(repeat),
+            'C' => self.apply_operator(Operator::Change, '$', repeat),
+            'u' => (0..repeat).for_each(|_| self.output.undo()),
+            'p' | 'P' => self.output.paste(&self.register, repeat, val == 'p'),
+            'o' | 'O' => {
+                self.output.open_line(val == 'o');
+                self.mode = Mode::Insert;
+            }
+            '/' => {
+                let reader = &self.reader;
+                self.output.jump(|output| output.find(reader))?
+            }
+            'n' | 'N' => self
+                .output
+                .jump(|output| (0..repeat).for_each(|_| output.find_next(val == 'N'))),
+            ':' => return self.process_command(),
+            '.' => return self.repeat_last_change(count),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn apply_operator(&mut self, operator: Operator, motion: char, count: usize) {
+        match (operator, motion) {
+            (Operator::Delete, 'd') => {
+                self.register = self.output.yank_lines(count);
+                let rows_below = self.output.editor_rows.number_of_rows()
+                    - self.output.cursor_controller.cursor_y;
+                (0..cmp::min(count, rows_below)).for_each(|_| self.output.delete_row());
+            }
+            (Operator::Yank, 'y') => {
+                self.register = self.output.yank_lines(count);
+                let lines = self.register.contents.len();
+                if lines > 2 {
+                    self.output.set_message(&format!("{} lines yanked", lines));
+                }
+            }
+            (Operator::Comment, 'c') => {
+                let row = self.output.cursor_controller.cursor_y;
+                self.output.toggle_comment(row..row + count);
+            }
+            (Operator::Indent, '>') | (Operator::Dedent, '<') => {
+                let row = self.output.cursor_controller.cursor_y;
+                self.output.shift_lines(row..row + count, operator == Operator::Indent);
+            }
+            (Operator::Upper, 'U') | (Operator::Lower, 'u') => {
+                let row = self.output.cursor_controller.cursor_y;
+                let case = if operator == Operator::Upper { Case::Upper } else { Case::Lower };
+                self.output.change_lines_case(row..row + count, case);
+            }
+            (Operator::Change, 'w') => {
+                if let Some(register) = self.output.change_word(count) {
+                    self.register = register;
+                }
+                self.mode = Mode::Insert;
+            }
+            _ => {
+                if let Some((start, end)) = self.output.motion_range(motion, count) {
+                    self.apply_operator_range(operator, start, end);
+                }
+            }
+        }
+    }
+
+    fn apply_text_object(&mut self, operator: Operator, object: char) {
+        let cursor = self.output.cursor_position();
+        if let Some((start, end)) = self.output.editor_rows.text_object(cursor, object) {
+            self.apply_operator_range(operator, start, end);
+        }
+    }
+
+    fn apply_operator_range(
+        &mut self,
+        operator: Operator,
+        start: (usize, usize),
+        end: (usize, usize)
+    ) {
+        let register = match operator {
+            Operator::Yank => self.output.yank_text(start, end),
+            Operator::Delete | Operator::Change => self.output.delete_text(start, end),
+            Operator::Comment => {
+                self.output.toggle_comment(start.0..end.0 + 1);
+                None
+            }
+            Operator::Indent | Operator::Dedent => {
+                self.output.shift_lines(start.0..end.0 + 1, operator == Operator::Indent);
+                None
+            }
+            Operator::Upper | Operator::Lower => {
+                let case = if operator == Operator::Upper { Case::Upper } else { Case::Lower };
+                self.output.change_case(start, end, case);
+                None
+            }
+        };
+        if let Some(register) = register {
+            self.register = register;
+        }
+        match operator {
+            Operator::Change => self.mode = Mode::Insert,
+            Operator::Delete | Operator::Yank | Operator::Upper | Operator::Lower => {
+                self.output.set_cursor_position(start)
+            }
+            Operator::Comment | Operator::Indent | Operator::Dedent => {}
+        }
+    }
+
+    fn process_visual_command(&mut self, val: char) {
+        match (val, self.mode) {
+            ('v', Mode::Visual) | ('V', Mode::VisualLine) => self.exit_visual_mode(),
+            ('v', _) => self.mode = Mode::Visual,
+            ('V', _) => self.mode = Mode::VisualLine,
+            ('>' | '<', _) => {
+                if let Some((start, end)) = self.output.selection(self.mode) {
+                    self.output.shift_lines(start.0..end.0 + 1, val == '>');
+                }
+                self.exit_visual_mode();
+            }
+            ('d' | 'x', _) => {
+                if let Some(register) = self.output.delete_selection(self.mode) {
+                    self.register = register;
+                }
+                self.exit_visual_mode();
+            }
+            ('y', _) => {
+                if let Some(register) = self.output.yank_selection(self.mode) {
+                    self.register = register;
+                }
+                self.exit_visual_mode();
+            }
+            ('U' | 'u' | '~', _) => {
+                let case = match val {
+                    'U' => Case::Upper,
+                    'u' => Case::Lower,
+                    _ => Case::Toggle
+                };
+                self.output.change_selection_case(self.mode, case);
+                self.exit_visual_mode();
+            }
+            ('I' | 'A', Mode::VisualBlock) => {
+                self.block_insert = self.output.start_block_insert(val == 'A');
+                self.change_start = Some(self.output.cursor_position());
+                self.mode = Mode::Insert;
+            }
+            _ => {}
+        }
+    }
+
+    fn exit_visual_mode(&mut self) {
+        self.mode = Mode::Normal;
+        self.output.visual_anchor = None;
+    }
+
+    fn process_command(&mut self) -> crossterm::Result<bool> {
+        let input = match self.output.prompt(&self.reader, ":", None)? {
+            Some(input) if !input.trim().is_empty() => input,
+            _ => return Ok(true)
+        };
+        let command = match Command::parse(&input) {
+            Some(command) => command,
+            None => {
+                self.output
+                    .set_message(&format!("E492: Not an editor command: {}", input.trim()));
+                return Ok(true);
+            }
+        };
+        match command {
+            Command::Quit | Command::ForceQuit if self.output.windows.len() > 1 => {
+                self.output.close_window()
+            }
+            Command::Quit => {
+                if self.output.editor_rows.dirty > 0 {
+                    self.output
+                        .set_message("E37: No write since last change (add ! to override)");
+                    return Ok(true);
+                }
+                return Ok(!self.can_quit());
+            }
+            Command::ForceQuit => return Ok(false),
+            Command::Write => {
+                self.output.save(&self.reader)?;
+            }
+            Command::WriteQuit if self.output.windows.len() > 1 => {
+                if self.output.save(&self.reader)? {
+                    self.output.close_window();
+                }
+            }
+            Command::WriteQuit => {
+                return Ok(!(self.output.save(&self.reader)? && self.can_quit()))
+            }
+            Command::Set(option) => self.output.set_option(&option),
+            Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                global
+            } => self.output.substitute(range, &pattern, &replacement, global),
+            Command::Goto(line) => self
+                .output
+                .jump(|output| output.goto_row(line.saturating_sub(1))),
+            Command::Edit(Some(path)) => {
+                self.edit_file(&path);
+            }
+            Command::View(path) => {
+                if path.as_deref().is_none_or(|path| self.edit_file(path)) {
+                    self.output.editor_rows.read_only = true;
+                }
+            }
+            Command::Edit(None) if self.output.editor_rows.dirty > 0 => self
+                .output
+                .set_message("E37: No write since last change (add ! to override)"),
+            Command::Edit(None) | Command::Reload => self.output.reload(),
+            Command::BufferNext => self.output.cycle_buffer(true),
+            Command::BufferPrevious => self.output.cycle_buffer(false),
+            Command::ListBuffers => self.output.list_buffers(),
+            Command::FileInfo => self.output.show_file_info(),
+            Command::Recover => self.output.recover(),
+            Command::Help => self.output.show_help(),
+            Command::Split(split) => self.output.split_window(split),
+            Command::Close => self.output.close_window(),
+            Command::Find(query) => self.find_file(query.as_deref())?
+        }
+        Ok(true)
+    }
+
+    fn edit_file(&mut self, path: &Path) -> bool {
+        if let Some(index) = self.output.find_buffer(path) {
+            self.output.switch_buffer(index);
+            return true;
+        }
+        match EditorRows::from_file(path) {
+            Ok(editor_rows) => {
+                self.output.add_buffer(editor_rows);
+                self.output.set_message(&format!(
+                    "\"{}\" {}L",
+                    path.display(),
+                    self.output.editor_rows.number_of_rows()
+                ));
+                self.output.offer_recovery();
+                true
+            }
+            Err(err) => {
+                self.output
+                    .set_message(&format!("Can't open {}: {}", path.display(), err));
+                false
+            }
+        }
+    }
+
+    fn jump_to_tag(&mut self) {
+        let Some(name) = self.output.editor_rows.word_at(self.output.cursor_position()) else {
+            self.output.set_message("E349: No identifier under cursor");
+            return;
+        };
+        let Ok(tags) = tags::load(Path::new(tags::FILE)) else {
+            self.output.set_message("E433: No tags file");
+            return;
+        };
+        let Some(tag) = tags::find(&tags, &name) else {
+            self.output.set_message(&format!("E426: Tag not found: {}", name));
+            return;
+        };
+        let origin = (self.output.current_buffer, self.output.cursor_position());
+        if self.edit_file(&tag.file) {
+            self.output.tag_stack.push(origin);
+            self.output.goto_tag(&tag.address);
+        }
+    }
+
+    fn find_file(&mut self, query: Option<&str>) -> crossterm::Result<()> {
+        let candidates = finder::files(Path::new("."), FIND_LIMIT);
+        let chosen = match query {
+            Some(query) => finder::rank(query, &candidates, 1).first().copied().ok_or(query.into()),
+            None => {
+                let mut matches = finder::rank("", &candidates, FIND_MATCHES);
+                let mut selected = 0;
+                self.output.prompt_hint = finder::summary(&candidates, &matches, selected);
+                let mut callback = |output: &mut Output, query: &str, key: KeyEvent| {
+                    let len = cmp::max(matches.len(), 1);
+                    match key.code {
+                        KeyCode::Enter | KeyCode::Esc => return,
+                        KeyCode::Tab | KeyCode::Down => selected = (selected + 1) % len,
+                        KeyCode::BackTab | KeyCode::Up => selected = (selected + len - 1) % len,
+                        _ => {
+                            matches = finder::rank(query, &candidates, FIND_MATCHES);
+                            selected = 0;
+                        }
+                    }
+                    output.prompt_hint = finder::summary(&candidates, &matches, selected);
+                };
+                let query = self.output.prompt(&self.reader, "Find: ", Some(&mut callback))?;
+                self.output.prompt_hint.clear();
+                match query {
+                    Some(query) => matches.get(selected).copied().ok_or(query),
+                    None => return Ok(())
+                }
+            }
+        };
+        match chosen {
+            Ok(index) => {
+                self.edit_file(Path::new(&candidates[index]));
+            }
+            Err(query) => self
+                .output
+                .set_message(&format!("E345: Can't find file \"{}\" in path", query))
+        }
+        Ok(())
+    }
+
+    fn can_quit(&mut self) -> bool {
+        match self.output.modified_buffer() {
+            Some(index) => {
+                let name = self.output.buffer_name(index);
+                self.output.set_message(&format!(
+                    "E162: No write since last change for buffer \"{}\"",
+                    name
+                ));
+                false
+            }
+            None => true
+        }
+    }
+
+    fn process_insert_key(&mut self, key: KeyEvent) {
+        match key {
+            KeyEvent {
+                code: KeyCode::Esc,
+                ..
+            } => {
+                self.mode = Mode::Normal;
+                match self.block_insert.take() {
+                    Some(block) => self.output.finish_block_insert(block),
+                    None => self.output.move_cursor('h')
+                }
+            }
+            KeyEvent {
+                code: KeyCode::Char(ch),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+                ..
+            } => self.output.type_char(ch),
+            KeyEvent {
+                code: KeyCode::Tab,
+                ..
+            } => self.output.insert_tab(),
+            KeyEvent {
+                code: KeyCode::Enter,
+                ..
+            } => self.output.insert_newline(),
+            KeyEvent {
+                code: KeyCode::Backspace,
+                ..
+            } => self.output.delete_char(),
+            KeyEvent {
+                code: KeyCode::Delete,
+                ..
+            } => self.output.delete_char_forward(),
+            _ => {}
+        }
+    }
+
+    fn run(&mut self) -> crossterm::Result<bool> {
+        if self.output.needs_redraw || self.output.status_message.is_expired() {
+            self.output.refresh_screen(self.mode)?;
+        }
+        self.process_keypress()
+    }
+}
+
+fn main() -> crossterm::Result<()> {
+    let (config, config_errors) = Config::load();
+    let (flags, files): (Vec<String>, Vec<String>) =
+        env::args().skip(1).partition(|arg| arg.starts_with('-') && arg != "-");
+    let mut editor_rows = match files.first() {
+        Some(file) if file == "-" => EditorRows::from_reader(io::stdin())?,
+        Some(file) => EditorRows::from_file(Path::new(file))?,
+        None if !io::stdin().is_terminal() => EditorRows::from_reader(io::stdin())?,
+        None => EditorRows::new()
+    };
+    editor_rows.read_only = flags.iter().any(|flag| flag == "-R");
+
+    let _clean_up = CleanUp;
+    terminal::enable_raw_mode()?;
+    execute!(
+        stdout(),
+        terminal::EnterAlternateScreen,
+        event::EnableMouseCapture,
+        event::EnableFocusChange
+    )?;
+
+    let mut editor = Editor::new(editor_rows, config, &config_errors);
+    while editor.run()? {}
+    let _ = editor.output.remember_positions();
+    editor.output.remove_swap_files();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Script(Rc<RefCell<VecDeque<Event>>>);
+
+    impl EventSource for Script {
+        fn next_event(&mut self, _timeout: Duration) -> crossterm::Result<Option<Event>> {
+            let event = self.0.borrow_mut().pop_front();
+            assert!(event.is_some(), "ran out of scripted keys");
+            Ok(event)
+        }
+    }
+
+    fn editor(text: &str) -> Editor {
+        sized_editor(text, (80, 22))
+    }
+
+    fn sized_editor(text: &str, win_size: (usize, usize)) -> Editor {
+        let mut output = Output::with_size(editor_rows(text), Config::default(), win_size);
+        output.editor_contents = EditorContents::with_writer(Box::new(io::sink()));
+        Editor::with_output(output)
+    }
+
+    fn configured_editor(contents: &str, config: &str) -> Editor {
+        let (config, errors) = Config::parse(config);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let mut output = Output::with_size(editor_rows(contents), config, (80, 22));
+        output.editor_contents = EditorContents::with_writer(Box::new(io::sink()));
+        Editor::with_output(output)
+    }
+
+    fn drawn_rows(output: &Output) -> Vec<&str> {
+        let contents = &output.editor_contents;
+        (0..contents.segments.len())
+            .filter_map(|index| segment_at(&contents.content, &contents.segments, index))
+            .map(|(_, segment)| segment)
+            .collect()
+    }
+
+    fn refresh(editor: &mut Editor) {
+        editor.output.refresh_screen(editor.mode).unwrap();
+    }
+
+    fn key(ch: char) -> KeyEvent {
+        match ch {
+            '\x1b' => KeyEvent::new(KeyCode::Esc, event::KeyModifiers::NONE),
+            '\r' => KeyEvent::new(KeyCode::Enter, event::KeyModifiers::NONE),
+            '\x7f' => KeyEvent::new(KeyCode::Backspace, event::KeyModifiers::NONE),
+            '\t' => KeyEvent::new(KeyCode::Tab, event::KeyModifiers::NONE),
+            '\x01'..='\x1a' => {
+                let letter = (ch as u8 - 1 + b'a') as char;
+                KeyEvent::new(KeyCode::Char(letter), event::KeyModifiers::CONTROL)
+            }
+            _ => KeyEvent::new(KeyCode::Char(ch), event::KeyModifiers::NONE)
+        }
+    }
+
+    fn script(editor: &mut Editor, keys: &[KeyEvent]) -> Script {
+        let script = Script::default();
+        script.0.borrow_mut().extend(keys.iter().map(|&key| Event::Key(key)));
+        *editor.reader.source.borrow_mut() = Box::new(script.clone());
+        script
+    }
+
+    fn press_keys(editor: &mut Editor, keys: &[KeyEvent]) {
+        let script = script(editor, keys);
+        while !script.0.borrow().is_empty() {
+            editor.process_keypress().unwrap();
+        }
+    }
+
+    fn press(editor: &mut Editor, keys: &str) {
+        press_keys(editor, &keys.chars().map(key).collect::<Vec<KeyEvent>>());
+    }
+
+    fn press_code(editor: &mut Editor, code: KeyCode) {
+        press_keys(editor, &[KeyEvent::new(code, event::KeyModifiers::NONE)]);
+    }
+
+    fn message(editor: &Editor) -> &str {
+        editor.output.status_message.message.as_deref().unwrap_or_default()
+    }
+
+    fn cursor(editor: &Editor) -> (usize, usize) {
+        let cursor = &editor.output.cursor_controller;
+        (cursor.cursor_y, cursor.cursor_x)
+    }
+
+    fn positions(editor: &mut Editor, keys: &str) -> Vec<(usize, usize)> {
+        keys.chars()
+            .map(|ch| {
+                press(editor, &ch.to_string());
+                let cursor = &editor.output.cursor_controller;
+                (cursor.cursor_y, cursor.cursor_x)
+            })
+            .collect()
+    }
+
+    fn lines(editor: &Editor) -> Vec<&str> {
+        editor.output.editor_rows.row_contents.iter().map(|row| row.row_content.as_str()).collect()
+    }
+
+    fn editor_rows(text: &str) -> EditorRows {
+        let mut editor_rows = EditorRows::new();
+        editor_rows.load(text);
+        editor_rows
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rusty-vim-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn cursor_stays_inside_window_corners() {
+        let editor_rows = editor_rows("ab\ncd");
+        let mut cursor = CursorController::new((3, 2));
+        cursor.move_cursor('h', &editor_rows);
+        cursor.move_cursor('k', &editor_rows);
+        assert_eq!((cursor.cursor_y, cursor.cursor_x), (0, 0));
+        (0..5).for_each(|_| cursor.move_cursor('l', &editor_rows));
+        (0..5).for_each(|_| cursor.move_cursor('j', &editor_rows));
+        assert_eq!((cursor.cursor_y, cursor.cursor_x), (1, 1));
+    }
+
+    #[test]
+    fn draw_welcome_on_narrow_screens() {
+        for columns in [0, 1, 5, 10] {
+            let mut output = Output::with_size(EditorRows::new(), Config::default(), (columns, 10));
+            output.draw_rows(Mode::Normal);
+            assert!(drawn_rows(&output)[0].starts_with('~'));
+        }
+    }
+
+    #[test]
+    fn draw_buffer_lines_before_tildes() {
+        let mut editor_rows = EditorRows::new();
+        editor_rows.row_contents = vec![
+            Row::new("first".into(), TAB_STOP),
+            Row::new("second".into(), TAB_STOP)
+        ];
+        let mut output = Output::with_size(editor_rows, Config::default(), (20, 4));
+        assert_eq!(output.editor_rows.number_of_rows(), 2);
+        assert_eq!(output.editor_rows.get_row(1), Some("second"));
+        assert_eq!(output.editor_rows.get_row(2), None);
+        output.draw_rows(Mode::Normal);
+        let rows = drawn_rows(&output);
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].starts_with("first"));
+        assert!(rows[1].starts_with("second"));
+        assert!(rows[2].starts_with('~'));
+    }
+
+    #[test]
+    fn open_file_from_path() {
+        let path = temp_path("open.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let editor_rows = EditorRows::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(editor_rows.number_of_rows(), 3);
+        assert_eq!(editor_rows.get_row(2), Some("three"));
+        let missing = EditorRows::from_file(&temp_path("missing.txt")).unwrap();
+        assert_eq!(missing.number_of_rows(), 0);
+        assert_eq!(missing.filename, Some(temp_path("missing.txt")));
+    }
+
+    #[test]
+    fn scroll_rows_to_follow_cursor() {
+        let editor_rows = editor_rows(&["line"; 20].join("\n"));
+        let mut cursor = CursorController::new((20, 5));
+        let mut offsets = Vec::new();
+        for direction in "jjjjjjjkkkkkk".chars() {
+            cursor.move_cursor(direction, &editor_rows);
+            cursor.scroll(&editor_rows);
+            offsets.push(cursor.row_offset);
+        }
+        assert_eq!(offsets, [0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 2, 1]);
+    }
+
+    #[test]
+    fn draw_visible_slice_of_long_lines() {
+        let editor_rows = editor_rows("0123456789abcdefghij\nxy");
+        let mut output = Output::with_size(editor_rows, Config::default(), (10, 4));
+        (0..12).for_each(|_| output.move_cursor('l'));
+        output.cursor_controller.scroll(&output.editor_rows);
+        output.draw_rows(Mode::Normal);
+        let rows = drawn_rows(&output);
+        assert!(rows[0].starts_with("3456789abc"));
+        assert!(!rows[1].contains('x'));
+        let cursor = &output.cursor_controller;
+        assert_eq!(cursor.cursor_x - cursor.column_offset, 9);
+    }
+
+    #[test]
+    fn status_bar_shows_name_lines_and_position() {
+        let mut output = Output::with_size(editor_rows("a\nb\nc"), Config::default(), (20, 4));
+        assert!(output.status_info(Mode::Normal).0.contains("[No Name] - 3 lines"));
+        output.editor_rows.filename = Some(PathBuf::from("notes.txt"));
+        output.move_cursor('j');
+        let (info, line_info) = output.status_info(Mode::Normal);
+        assert!(info.contains("notes.txt - 3 lines"));
+        assert!(line_info.ends_with("2:1"));
+    }
+
+    #[test]
+    fn expired_message_renders_empty() {
+        let mut message = StatusMessage::new("HELP".into());
+        assert_eq!(message.message().map(String::as_str), Some("HELP"));
+        message.set_time = Some(Instant::now() - Duration::from_secs(6));
+        assert_eq!(message.message(), None);
+        let missing = EditorRows::from_file(&temp_path("new.txt")).unwrap();
+        let mut output = Output::with_size(missing, Config::default(), (80, 22));
+        let shown = output.status_message.message().cloned().unwrap();
+        assert!(shown.ends_with("\" [New]"));
+    }
+
+    #[test]
+    fn insert_mode_types_at_cursor() {
+        let mut editor = editor("ad");
+        press(&mut editor, "ibc");
+        assert_eq!(editor.mode, Mode::Insert);
+        assert!(editor.output.status_info(editor.mode).0.starts_with("INSERT"));
+        assert_eq!(lines(&editor), ["bcad"]);
+        assert_eq!(editor.output.cursor_controller.cursor_x, 2);
+        press(&mut editor, "\x1b");
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.output.cursor_controller.cursor_x, 1);
+    }
+
+    #[test]
+    fn backspace_and_delete_in_insert_mode() {
+        let mut editor = editor("abc\ndef");
+        press(&mut editor, "li\x7f");
+        assert_eq!(lines(&editor), ["bc", "def"]);
+        press(&mut editor, "\x7f");
+        assert_eq!(lines(&editor), ["bc", "def"]);
+        press_code(&mut editor, KeyCode::Delete);
+        assert_eq!(lines(&editor), ["c", "def"]);
+        press(&mut editor, "\x1bjli\x7f\x7f");
+        assert_eq!(lines(&editor), ["cef"]);
+        let cursor = &editor.output.cursor_controller;
+        assert_eq!((cursor.cursor_y, cursor.cursor_x), (0, 1));
+        (0..3).for_each(|_| press_code(&mut editor, KeyCode::Delete));
+        assert_eq!(lines(&editor), ["c"]);
+    }
+
+    #[test]
+    fn enter_splits_line_at_cursor() {
+        let mut empty = editor("");
+        press(&mut empty, "iab\r");
+        assert_eq!(lines(&empty), ["ab", ""]);
+        assert_eq!(empty.output.editor_rows.number_of_rows(), 2);
+        let mut editor = editor("abcd");
+        press(&mut editor, "lli\r");
+        assert_eq!(lines(&editor), ["ab", "cd"]);
+        let cursor = &editor.output.cursor_controller;
+        assert_eq!((cursor.cursor_y, cursor.cursor_x), (1, 0));
+        press(&mut editor, "\r");
+        assert_eq!(lines(&editor), ["ab", "", "cd"]);
+        let cursor = &editor.output.cursor_controller;
+        assert_eq!((cursor.cursor_y, cursor.cursor_x), (2, 0));
+    }
+
+    #[test]
+    fn ctrl_s_writes_buffer_to_disk() {
+        let path = temp_path("save.txt");
+        let mut editor = editor("one\ntwo\n");
+        editor.output.editor_rows.filename = Some(path.clone());
+        press(&mut editor, "ix\x1b\x13");
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "xone\ntwo\n");
+        assert_eq!(message(&editor), "9 bytes written to disk");
+    }
+
+    #[test]
+    fn quit_needs_repeated_ctrl_q_while_dirty() {
+        let mut editor = editor("a");
+        press(&mut editor, "ix\x1b");
+        assert!(editor.output.editor_rows.dirty > 0);
+        for _ in 0..QUIT_TIMES {
+            assert!(editor.process_key(key('\x11')).unwrap());
+        }
+        press(&mut editor, "l");
+        assert!(editor.process_key(key('\x11')).unwrap());
+        assert_eq!(editor.quit_times, QUIT_TIMES - 1);
+        (1..QUIT_TIMES).for_each(|_| press(&mut editor, "\x11"));
+        assert!(!editor.process_key(key('\x11')).unwrap());
+    }
+
+    #[test]
+    fn quit_after_save_without_warning() {
+        let path = temp_path("quit.txt");
+        let mut editor = editor("a");
+        editor.output.editor_rows.filename = Some(path.clone());
+        press(&mut editor, "ix\x1b\x13");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(editor.output.editor_rows.dirty, 0);
+        assert!(!editor.process_key(key('\x11')).unwrap());
+    }
+
+    #[test]
+    fn word_motions_cross_lines() {
+        let mut editor = editor("foo bar.baz\n  qux");
+        assert_eq!(positions(&mut editor, "wwww"), [(0, 4), (0, 7), (0, 8), (1, 2)]);
+        assert_eq!(positions(&mut editor, "bbbb"), [(0, 8), (0, 7), (0, 4), (0, 0)]);
+        assert_eq!(positions(&mut editor, "eeeee"), [(0, 2), (0, 6), (0, 7), (0, 10), (1, 4)]);
+    }
+
+    #[test]
+    fn line_jump_motions() {
+        let mut editor = editor("hello\n\nlong line");
+        assert_eq!(positions(&mut editor, "$0"), [(0, 4), (0, 0)]);
+        assert_eq!(positions(&mut editor, "$G"), [(0, 4), (2, 8)]);
+        press(&mut editor, "$k");
+        assert_eq!(positions(&mut editor, "$"), [(1, 0)]);
+        assert_eq!(positions(&mut editor, "Ggg"), [(2, 8), (2, 8), (0, 4)]);
+        let mut single = Output::with_size(editor_rows("only"), Config::default(), (80, 22));
+        single.move_cursor('G');
+        assert_eq!((single.cursor_controller.cursor_y, single.cursor_controller.cursor_x), (0, 0));
+    }
+
+    #[test]
+    fn vertical_motion_remembers_goal_column() {
+        let mut editor = editor("long line here\nab\nanother long line");
+        press(&mut editor, &"l".repeat(10));
+        assert_eq!(positions(&mut editor, "jj"), [(1, 1), (2, 10)]);
+        press(&mut editor, "k");
+        assert_eq!(editor.output.cursor_controller.goal_column(&editor.output.editor_rows), 10);
+        assert_eq!(positions(&mut editor, "hk"), [(1, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn page_down_and_up() {
+        let contents = ["line"; 30].join("\n");
+        let output = Output::with_size(editor_rows(&contents), Config::default(), (20, 6));
+        let mut editor = Editor::with_output(output);
+        let mut pages = Vec::new();
+        for code in [[KeyCode::PageDown; 5], [KeyCode::PageUp; 5]].concat() {
+            press_code(&mut editor, code);
+            editor.output.cursor_controller.scroll(&editor.output.editor_rows);
+            let cursor = &editor.output.cursor_controller;
+            pages.push((cursor.cursor_y, cursor.row_offset));
+        }
+        assert_eq!(
+            pages,
+            [(11, 6), (17, 12), (23, 18), (29, 24), (29, 24), (18, 18), (12, 12), (6, 6), (0, 0), (0, 0)]
+        );
+    }
+
+    #[test]
+    fn home_and_end_keys() {
+        let mut editor = editor(&format!("\nabc\n{}", "x".repeat(200)));
+        let mut ends = Vec::new();
+        for _ in 0..3 {
+            press_code(&mut editor, KeyCode::End);
+            ends.push(cursor(&editor));
+            press_code(&mut editor, KeyCode::Home);
+            ends.push(cursor(&editor));
+            press(&mut editor, "j");
+        }
+        assert_eq!(ends, [(0, 0), (0, 0), (1, 2), (1, 0), (2, 199), (2, 0)]);
+        press(&mut editor, "kki");
+        let mut ends = Vec::new();
+        for _ in 0..3 {
+            press_code(&mut editor, KeyCode::End);
+            ends.push(cursor(&editor));
+            press_code(&mut editor, KeyCode::Home);
+            ends.push(cursor(&editor));
+            press(&mut editor, "\x1bji");
+        }
+        assert_eq!(ends, [(0, 0), (0, 0), (1, 3), (1, 0), (2, 200), (2, 0)]);
+    }
+
+    #[test]
+    fn tabs_render_to_next_tab_stop() {
+        let row = Row::new("\tab  \tc".into(), TAB_STOP);
+        assert_eq!(row.render, format!("{}ab{}c", " ".repeat(8), " ".repeat(6)));
+        assert_eq!([1, 3, 5, 6].map(|cursor_x| row.cx_to_rx(cursor_x, TAB_STOP)), [8, 10, 12, 16]);
+        let cursor_xs = [0, 3, 8, 12, 15, 16].map(|render_x| row.rx_to_cx(render_x, TAB_STOP));
+        assert_eq!(cursor_xs, [0, 0, 1, 5, 5, 6]);
+    }
+
+    #[test]
+    fn clean_up_never_panics_without_terminal() {
+        drop(CleanUp);
+    }
+
+    #[test]
+    fn find_searches_forward_and_wraps() {
+        let editor_rows = editor_rows("alpha\nbeta\ngamma beta");
+        let find = |query: &str, from| {
+            let pattern = Pattern::new(query, false).unwrap();
+            editor_rows.find(&pattern, from, SearchDirection::Forward)
+        };
+        assert_eq!(find("beta", (1, 1)), Some(((2, 6), 4)));
+        assert_eq!(find("alp", (2, 6)), Some(((0, 0), 3)));
+        assert_eq!(find("a", (0, 0)), Some(((0, 4), 1)));
+        assert_eq!(find("delta", (0, 0)), None);
+    }
+
+    #[test]
+    fn next_and_previous_match_wrap_around() {
+        let mut editor = editor("x foo\nfoo\nbar\nfoo foo");
+        press(&mut editor, "n");
+        assert_eq!(message(&editor), "E35: No previous regular expression");
+        editor.output.search_state.query = "foo".into();
+        assert_eq!(positions(&mut editor, "nnnnn"), [(0, 2), (1, 0), (3, 0), (3, 4), (0, 2)]);
+        assert_eq!(message(&editor), "search hit BOTTOM, continuing at TOP");
+        assert_eq!(positions(&mut editor, "NN"), [(3, 4), (3, 0)]);
+        assert_eq!(message(&editor), "/foo");
+        let editor_rows = editor_rows("x foo\nfoo");
+        let pattern = Pattern::new("foo", false).unwrap();
+        assert_eq!(editor_rows.find(&pattern, (1, 0), SearchDirection::Backward), Some(((0, 2), 3)));
+    }
+
+    #[test]
+    fn prompt_reads_scripted_keys() {
+        let mut editor = editor("");
+        script(&mut editor, &"abx\x7fc\r".chars().map(key).collect::<Vec<KeyEvent>>());
+        let input = editor.output.prompt(&editor.reader, "> ", None).unwrap();
+        assert_eq!(input.as_deref(), Some("abc"));
+        script(&mut editor, &[key('a'), key('\x1b')]);
+        assert_eq!(editor.output.prompt(&editor.reader, "> ", None).unwrap(), None);
+        let mut seen = Vec::new();
+        let mut callback = |_: &mut Output, input: &str, _: KeyEvent| seen.push(input.to_string());
+        script(&mut editor, &[key('x'), key('y'), key('\r')]);
+        editor.output.prompt(&editor.reader, "> ", Some(&mut callback)).unwrap();
+        assert_eq!(seen, ["x", "xy", "xy"]);
+    }
+
+    #[test]
+    fn incremental_search_moves_and_restores_cursor() {
+        let mut editor = editor("alpha\nbeta\ngamma beta");
+        press(&mut editor, "jl/beta\r");
+        assert_eq!(cursor(&editor), (2, 6));
+        press(&mut editor, "/alp\r");
+        assert_eq!(cursor(&editor), (0, 0));
+        press(&mut editor, "/gam\x1b");
+        assert_eq!(cursor(&editor), (0, 0));
+        press(&mut editor, "/delta\r");
+        assert_eq!(cursor(&editor), (0, 0));
+        assert_eq!(message(&editor), "E486: Pattern not found: delta");
+    }
+
+    #[test]
+    fn save_as_prompts_for_a_file_name() {
+        let mut editor = editor("fn main() {}");
+        press(&mut editor, "\x13\x1b");
+        assert_eq!(message(&editor), "Save aborted");
+        let path = temp_path("save_as.rs");
+        press(&mut editor, &format!("\x13{}\r", path.display()));
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "fn main() {}");
+        assert_eq!(editor.output.editor_rows.filename, Some(path));
+        assert_eq!(editor.output.editor_rows.syntax.name(), "rust");
+    }
+
+    #[test]
+    fn parse_ex_commands() {
+        assert_eq!(Command::parse("q"), Some(Command::Quit));
+        assert_eq!(Command::parse(" q! "), Some(Command::ForceQuit));
+        assert_eq!(Command::parse("w"), Some(Command::Write));
+        assert_eq!(Command::parse("wq"), Some(Command::WriteQuit));
+        assert_eq!(Command::parse("e  notes.txt"), Some(Command::Edit(Some("notes.txt".into()))));
+        assert_eq!(Command::parse("frobnicate"), None);
+        let mut editor = editor("");
+        press(&mut editor, ":frobnicate\r");
+        assert_eq!(message(&editor), "E492: Not an editor command: frobnicate");
+    }
+
+    #[test]
+    fn quit_command_honours_dirty_check() {
+        let mut editor = editor("a");
+        press(&mut editor, "ix\x1b");
+        script(&mut editor, &":q\r".chars().map(key).collect::<Vec<KeyEvent>>());
+        assert!(editor.process_keypress().unwrap());
+        assert_eq!(message(&editor), "E37: No write since last change (add ! to override)");
+        script(&mut editor, &":q!\r".chars().map(key).collect::<Vec<KeyEvent>>());
+        assert!(!editor.process_keypress().unwrap());
+    }
+
+    #[test]
+    fn gutter_width_follows_line_count() {
+        let widths = [9, 10, 100, 1000].map(|count| {
+            let editor_rows = editor_rows(&vec!["x"; count].join("\n"));
+            let mut output = Output::with_size(editor_rows, Config::default(), (80, 5));
+            assert_eq!(output.gutter_width(), 0);
+            output.line_numbers = LineNumbers::Absolute;
+            output.gutter_width()
+        });
+        assert_eq!(widths, [2, 3, 4, 5]);
+        let mut output = Output::with_size(editor_rows("a\nb"), Config::default(), (20, 4));
+        output.set_option("nu");
+        output.draw_rows(Mode::Normal);
+        let rows = drawn_rows(&output);
+        assert!(rows[0].starts_with("1 a"));
+        assert!(rows[1].starts_with("2 b"));
+        assert!(rows[2].starts_with("  ~"));
+    }
+
+    #[test]
+    fn relative_numbers_count_from_cursor_line() {
+        let editor_rows = editor_rows(&["x"; 7].join("\n"));
+        let mut output = Output::with_size(editor_rows, Config::default(), (20, 7));
+        output.goto_row(3);
+        let numbers = |output: &Output| (0..7).map(|row| output.line_number(row)).collect::<Vec<_>>();
+        output.set_option("rnu");
+        assert_eq!(numbers(&output), [3, 2, 1, 0, 1, 2, 3]);
+        output.set_option("nu");
+        assert_eq!(output.line_numbers, LineNumbers::Hybrid);
+        assert_eq!(numbers(&output), [3, 2, 1, 4, 1, 2, 3]);
+        output.draw_rows(Mode::Normal);
+        let rows = drawn_rows(&output);
+        assert!(rows[3].starts_with("4 x"));
+        output.move_cursor('j');
+        assert_eq!(numbers(&output), [4, 3, 2, 1, 5, 1, 2]);
+    }
+
+    #[test]
+    fn dd_deletes_current_line() {
+        let mut editor = editor("one\n  two\nthree\nfour");
+        press(&mut editor, "dd");
+        assert_eq!(lines(&editor), ["  two", "three", "four"]);
+        assert_eq!(cursor(&editor), (0, 0));
+        press(&mut editor, "jdd");
+        assert_eq!(lines(&editor), ["  two", "four"]);
+        assert_eq!(cursor(&editor), (1, 0));
+        press(&mut editor, "dd");
+        assert_eq!(lines(&editor), ["  two"]);
+        assert_eq!(cursor(&editor), (0, 0));
+        assert!(editor.output.editor_rows.dirty > 0);
+        press(&mut editor, "dd");
+        assert_eq!(lines(&editor), [""]);
+    }
+
+    #[test]
+    fn open_line_below_and_above() {
+        let mut editor = sized_editor("a\nb\nc", (20, 3));
+        press(&mut editor, "jjo");
+        assert_eq!(lines(&editor), ["a", "b", "c", ""]);
+        assert_eq!(editor.mode, Mode::Insert);
+        assert_eq!(cursor(&editor), (3, 0));
+        refresh(&mut editor);
+        assert_eq!(editor.output.cursor_controller.row_offset, 1);
+        press(&mut editor, "\x1bggO");
+        assert_eq!(lines(&editor), ["", "a", "b", "c", ""]);
+        assert_eq!(editor.mode, Mode::Insert);
+        assert_eq!(cursor(&editor), (0, 0));
+    }
+
+    #[test]
+    fn x_deletes_chars_under_cursor() {
+        let mut editor = editor("abcdef\n\n");
+        press(&mut editor, "lx");
+        assert_eq!(lines(&editor), ["acdef", ""]);
+        assert_eq!(cursor(&editor), (0, 1));
+        press(&mut editor, "2x");
+        assert_eq!(lines(&editor), ["aef", ""]);
+        press(&mut editor, "$x");
+        assert_eq!(lines(&editor), ["ae", ""]);
+        assert_eq!(cursor(&editor), (0, 1));
+        press(&mut editor, "5x");
+        assert_eq!(lines(&editor), ["a", ""]);
+        press(&mut editor, "jx");
+        assert_eq!(lines(&editor), ["a", ""]);
+        assert_eq!(cursor(&editor), (1, 0));
+    }
+
+    #[test]
+    fn count_prefix_repeats_motions() {
+        let mut editor = editor(&["one two three four five"; 8].join("\n"));
+        press(&mut editor, "5j");
+        assert_eq!(cursor(&editor), (5, 0));
+        press(&mut editor, "3w");
+        assert_eq!(cursor(&editor), (5, 14));
+        press(&mut editor, "0");
+        assert_eq!(cursor(&editor), (5, 0));
+        press(&mut editor, "10l");
+        assert_eq!(cursor(&editor), (5, 10));
+        press(&mut editor, "2\x1bk");
+        assert_eq!(cursor(&editor), (4, 10));
+        press(&mut editor, "3G");
+        assert_eq!(cursor(&editor), (2, 10));
+    }
+
+    #[test]
+    fn dd_count_stops_at_the_last_line() {
+        let mut editor = editor("one\ntwo\nthree\nfour");
+        press(&mut editor, "2j5dd");
+        assert_eq!(lines(&editor), ["one", "two"]);
+        assert_eq!(cursor(&editor), (1, 0));
+    }
+
+    #[test]
+    fn undo_restores_each_prior_state() {
+        let mut editor = editor("abc\ndef");
+        press(&mut editor, "lixy\x1b");
+        press(&mut editor, "jdd");
+        press(&mut editor, "o!\x1b");
+        press(&mut editor, "$x");
+        let mut states = vec![lines(&editor).join("|")];
+        for _ in 0..4 {
+            press(&mut editor, "u");
+            states.push(lines(&editor).join("|"));
+        }
+        assert_eq!(states, ["axybc|", "axybc|!", "axybc", "axybc|def", "abc|def"]);
+        assert_eq!(editor.output.cursor_position(), (0, 1));
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["abc", "def"]);
+    }
+
+    #[test]
+    fn redo_reapplies_until_a_new_edit() {
+        let mut editor = editor("abc");
+        press(&mut editor, "x");
+        press(&mut editor, "x");
+        press(&mut editor, "uu");
+        assert_eq!(lines(&editor), ["abc"]);
+        press(&mut editor, "\x12");
+        assert_eq!(lines(&editor), ["bc"]);
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+        press(&mut editor, "u\x12\x12");
+        assert_eq!(lines(&editor), ["c"]);
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["bc"]);
+        press(&mut editor, "ix\x1b\x12");
+        assert_eq!(lines(&editor), ["xbc"]);
+        assert_eq!(message(&editor), "Already at newest change");
+        press(&mut editor, "u\x12");
+        assert_eq!(lines(&editor), ["xbc"]);
+    }
+
+    #[test]
+    fn yank_and_paste_lines() {
+        let mut editor = editor("a\nb\nc");
+        press(&mut editor, "yyjp");
+        assert_eq!(lines(&editor), ["a", "b", "a", "c"]);
+        assert_eq!(editor.output.cursor_position(), (2, 0));
+        assert!(editor.output.editor_rows.dirty > 0);
+        press(&mut editor, "gg3yyG2p");
+        assert_eq!(lines(&editor), ["a", "b", "a", "c", "a", "b", "a", "a", "b", "a"]);
+        assert_eq!(editor.output.cursor_position(), (4, 0));
+        assert_eq!(editor.register.contents, ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn visual_selection_spans_lines_in_either_direction() {
+        let mut editor = editor("abcd\nefgh\nijkl");
+        press(&mut editor, "lvl");
+        assert_eq!(editor.output.selection(Mode::Visual), Some(((0, 1), (0, 2))));
+        assert_eq!(editor.output.selected_columns(Mode::Visual, 0), Some((1, 3)));
+        press(&mut editor, "\x1bjjvkkh");
+        assert_eq!(editor.output.selection(Mode::Visual), Some(((0, 1), (2, 2))));
+        assert_eq!(editor.output.selected_columns(Mode::Visual, 1), Some((0, 5)));
+        press(&mut editor, "y");
+        assert_eq!(editor.register.contents, ["bcd", "efgh", "ijk"]);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.output.cursor_position(), (0, 1));
+        press(&mut editor, "lvjd");
+        assert_eq!(lines(&editor), ["abh", "ijkl"]);
+        press(&mut editor, "vj\x1b");
+        assert_eq!(editor.output.selection(Mode::Visual), None);
+        assert_eq!(lines(&editor), ["abh", "ijkl"]);
+    }
+
+    #[test]
+    fn visual_line_selects_whole_rows() {
+        let mut editor = editor("aa\nbbbb\ncc\ndd");
+        let selected = |editor: &Editor| {
+            (0..4)
+                .filter(|&row| editor.output.selected_columns(Mode::VisualLine, row).is_some())
+                .collect::<Vec<_>>()
+        };
+        press(&mut editor, "jlVj");
+        assert_eq!(selected(&editor), [1, 2]);
+        assert_eq!(editor.output.selected_columns(Mode::VisualLine, 1), Some((0, 5)));
+        press(&mut editor, "kk");
+        assert_eq!(selected(&editor), [0, 1]);
+        press(&mut editor, "y");
+        assert_eq!(editor.register.contents, ["aa", "bbbb"]);
+        assert!(editor.register.linewise);
+        press(&mut editor, "jjVkd");
+        assert_eq!(lines(&editor), ["aa", "dd"]);
+    }
+
+    #[test]
+    fn highlight_cache_resets_when_row_changes() {
+        let mut editor_rows = editor_rows("let x = 1;\nfn");
+        editor_rows.set_filename(PathBuf::from("main.rs"));
+        editor_rows.update_highlight(0);
+        editor_rows.update_highlight(1);
+        assert_eq!(editor_rows.get_highlight(1), [HighlightType::Keyword; 2]);
+        editor_rows.insert_char(0, 0, '1');
+        assert!(editor_rows.row_contents[0].highlight.is_none());
+        assert!(editor_rows.row_contents[1].highlight.is_some());
+        editor_rows.update_highlight(0);
+        assert_eq!(editor_rows.get_highlight(0)[0], HighlightType::Number);
+        assert_eq!(editor_rows.get_highlight(0)[1..4], [HighlightType::Normal; 3]);
+    }
+
+    #[test]
+    fn search_match_overrides_syntax_color() {
+        let colored = |highlight: HighlightType, text: &str| {
+            style::SetForegroundColor(highlight.color()).to_string() + text
+        };
+        let mut editor = sized_editor("let fn = 1;", (40, 3));
+        editor.output.editor_rows.set_filename(PathBuf::from("main.rs"));
+        editor.output.search_state.highlighted_match = Some(((0, 4), 2));
+        editor.output.draw_rows(Mode::Normal);
+        let drawn = mem::take(&mut editor.output.editor_contents.content);
+        assert!(drawn.contains(&colored(HighlightType::Keyword, "let")));
+        assert!(drawn.contains(&colored(HighlightType::Match, "fn")));
+        assert!(drawn.contains(&colored(HighlightType::Normal, " = ")));
+        editor.output.search_state.highlighted_match = None;
+        editor.output.draw_rows(Mode::Normal);
+        let drawn = mem::take(&mut editor.output.editor_contents.content);
+        assert!(drawn.contains(&colored(HighlightType::Keyword, "fn")));
+    }
+
+    #[test]
+    fn search_match_is_forgotten_when_search_ends() {
+        let mut editor = editor("alpha beta");
+        press(&mut editor, "/beta\r");
+        assert_eq!(cursor(&editor), (0, 6));
+        assert!(editor.output.search_state.highlighted_match.is_none());
+        press(&mut editor, "/alpha\x1b");
+        assert!(editor.output.search_state.highlighted_match.is_none());
+    }
+
+    #[test]
+    fn config_sets_tab_width_and_quit_times() {
+        let config = Config { tab_width: 4, quit_times: 1, ..Config::default() };
+        let mut output = Output::with_size(editor_rows("\tx"), config, (80, 22));
+        output.editor_contents = EditorContents::with_writer(Box::new(io::sink()));
+        let mut editor = Editor::with_output(output);
+        assert_eq!(editor.output.editor_rows.cx_to_rx(0, 1), 4);
+        press(&mut editor, "iy\x1b");
+        assert!(editor.process_key(key('\x11')).unwrap());
+        assert!(!editor.process_key(key('\x11')).unwrap());
+    }
+
+    #[test]
+    fn remapped_keys_drive_the_editor() {
+        let (config, _) = Config::parse("[keys]\nn = \"down\"");
+        let mut editor = editor("a\nb\nc");
+        editor.output.config = config;
+        press(&mut editor, "n");
+        assert_eq!(cursor(&editor), (1, 0));
+        press(&mut editor, "in\x1b");
+        assert_eq!(lines(&editor)[1], "nb");
+    }
+
+    #[test]
+    fn tab_inserts_spaces_with_expand_tab() {
+        let mut editor = configured_editor("abc", "tab_width = 4\nexpand_tab = true");
+        press(&mut editor, "i\t\x1b$i\t");
+        assert_eq!(lines(&editor), ["    ab  c"]);
+        assert_eq!(editor.output.cursor_position(), (0, 8));
+        let mut editor = configured_editor("abc", "tab_width = 4");
+        press(&mut editor, "li\t");
+        assert_eq!(lines(&editor), ["a\tbc"]);
+        assert_eq!(editor.output.editor_rows.cx_to_rx(0, 2), 4);
+    }
+
+    #[test]
+    fn enter_copies_indentation_with_auto_indent() {
+        let mut editor = configured_editor("\t  foo(bar)", "auto_indent = true");
+        press(&mut editor, "$i\r");
+        assert_eq!(lines(&editor), ["\t  foo(bar", "\t  )"]);
+        assert_eq!(editor.output.cursor_position(), (1, 3));
+        press(&mut editor, "\x1bgg0li\r");
+        assert_eq!(lines(&editor), ["\t", "\tfoo(bar", "\t  )"]);
+        assert_eq!(editor.output.cursor_position(), (1, 1));
+        let mut plain = configured_editor("  foo", "auto_indent = false");
+        press(&mut plain, "$i\r");
+        assert_eq!(lines(&plain), ["  fo", "o"]);
+    }
+
+    #[test]
+    fn resize_updates_layout_and_keeps_cursor_visible() {
+        let mut editor = editor(&vec!["x"; 30].join("\n"));
+        press(&mut editor, "20j");
+        refresh(&mut editor);
+        assert_eq!(editor.output.cursor_controller.row_offset, 0);
+        let script = script(&mut editor, &[]);
+        script.0.borrow_mut().push_back(Event::Resize(40, 12));
+        assert!(editor.process_keypress().unwrap());
+        assert_eq!(editor.output.win_size, (40, 10));
+        assert_eq!(editor.output.cursor_controller.row_offset, 11);
+        assert_eq!(cursor(&editor), (20, 0));
+        editor.output.resize(40, 1);
+        assert_eq!(editor.output.win_size, (40, 1));
+        assert_eq!(editor.output.cursor_controller.row_offset, 20);
+    }
+
+    #[test]
+    fn screen_to_buffer_accounts_for_gutter_and_offsets() {
+        let editor_rows = editor_rows(&["0123456789"; 20].join("\n"));
+        let mut cursor = CursorController::new((5, 5));
+        assert_eq!(cursor.screen_to_buffer((3, 2), 0, &editor_rows), (2, 3));
+        assert_eq!(cursor.screen_to_buffer((3, 2), 2, &editor_rows), (2, 1));
+        (cursor.row_offset, cursor.column_offset) = (6, 4);
+        assert_eq!(cursor.screen_to_buffer((3, 2), 2, &editor_rows), (8, 5));
+        assert_eq!(cursor.screen_to_buffer((0, 0), 2, &editor_rows), (6, 4));
+        cursor.row_offset = 18;
+        assert_eq!(cursor.screen_to_buffer((3, 4), 0, &editor_rows), (19, 7));
+    }
+
+    #[test]
+    fn click_clamps_to_line_end_and_last_line() {
+        let mut editor = sized_editor("abc\n\nlonger line", (20, 10));
+        let click = |column, row| {
+            Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Down(MouseButton::Left),
+                column,
+                row,
+                modifiers: event::KeyModifiers::NONE
+            })
+        };
+        let script = script(&mut editor, &[]);
+        script.0.borrow_mut().push_back(click(10, 0));
+        editor.process_keypress().unwrap();
+        assert_eq!(cursor(&editor), (0, 2));
+        editor.output.click(10, 0, true);
+        assert_eq!(cursor(&editor), (0, 3));
+        editor.output.click(4, 8, false);
+        assert_eq!(cursor(&editor), (2, 4));
+        editor.output.line_numbers = LineNumbers::Absolute;
+        editor.output.click(4, 1, false);
+        assert_eq!(cursor(&editor), (1, 0));
+        editor.output.click(4, 2, false);
+        assert_eq!(cursor(&editor), (2, 2));
+    }
+
+    #[test]
+    fn wheel_scrolls_view_within_buffer() {
+        let mut editor = configured_editor(&["x"; 30].join("\n"), "scroll_step = 4");
+        editor.output.resize(80, 12);
+        let mut offsets = Vec::new();
+        for down in [true, true, true, true, true, false, false, false, false, false, false] {
+            editor.output.scroll_wheel(down);
+            refresh(&mut editor);
+            offsets.push(editor.output.cursor_controller.row_offset);
+        }
+        assert_eq!(offsets, [4, 8, 12, 16, 20, 16, 12, 8, 4, 0, 0]);
+        assert_eq!(cursor(&editor), (0, 0));
+        (0..3).for_each(|_| editor.output.scroll_wheel(true));
+        press(&mut editor, "j");
+        refresh(&mut editor);
+        assert_eq!(editor.output.cursor_controller.row_offset, 1);
+    }
+
+    #[test]
+    fn edit_multibyte_characters() {
+        let mut editor = editor("héllo");
+        press(&mut editor, "lliñ");
+        assert_eq!(lines(&editor), ["héñllo"]);
+        assert_eq!(editor.output.cursor_position(), (0, 3));
+        press(&mut editor, "\x7f\x7f\r");
+        assert_eq!(lines(&editor), ["h", "llo"]);
+        press(&mut editor, "\x7fé\x1bx");
+        assert_eq!(lines(&editor), ["hllo"]);
+        assert_eq!(editor.output.editor_rows.row_len(0), 4);
+        assert_eq!(editor.output.editor_rows.slice(0, 1, 3), "ll");
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        let mut editor = sized_editor("a漢字b\tc", (40, 3));
+        let render_x = |cursor_x| editor.output.editor_rows.cx_to_rx(0, cursor_x);
+        assert_eq!([0, 1, 2, 3, 4, 5].map(render_x), [0, 1, 3, 5, 6, 8]);
+        assert_eq!(editor.output.editor_rows.rx_to_cx(0, 2), 1);
+        press(&mut editor, "$");
+        editor.output.cursor_controller.scroll(&editor.output.editor_rows);
+        assert_eq!(editor.output.cursor_controller.render_x, 8);
+        editor.output.draw_rows(Mode::Normal);
+        assert!(editor.output.editor_contents.content.contains("a漢字b  c"));
+    }
+
+    #[test]
+    fn replace_characters_with_r() {
+        let mut editor = editor("abcdef\n\nxy");
+        press(&mut editor, "lrZ");
+        assert_eq!(lines(&editor), ["aZcdef", "", "xy"]);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.output.cursor_position(), (0, 1));
+        press(&mut editor, "l3r1");
+        assert_eq!(lines(&editor), ["aZ111f", "", "xy"]);
+        assert_eq!(editor.output.cursor_position(), (0, 4));
+        press(&mut editor, "jrq");
+        assert_eq!(lines(&editor), ["aZ111f", "", "xy"]);
+        press(&mut editor, "j5rq");
+        assert_eq!(lines(&editor), ["aZ111f", "", "xy"]);
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["aZcdef", "", "xy"]);
+    }
+
+    #[test]
+    fn join_lines_with_j() {
+        let mut editor = editor("foo\n    bar\nbaz\n\tqux\nend");
+        press(&mut editor, "J");
+        assert_eq!(lines(&editor), ["foo bar", "baz", "\tqux", "end"]);
+        assert_eq!(editor.output.cursor_position(), (0, 3));
+        assert!(editor.output.editor_rows.dirty > 0);
+        press(&mut editor, "j3J");
+        assert_eq!(lines(&editor), ["foo bar", "baz qux end"]);
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["foo bar", "baz", "\tqux", "end"]);
+    }
+
+    #[test]
+    fn change_word_and_change_to_line_end() {
+        let mut editor = editor("one two  three\nlast");
+        press(&mut editor, "cwuno");
+        assert_eq!(editor.mode, Mode::Insert);
+        assert_eq!(lines(&editor), ["uno two  three", "last"]);
+        assert_eq!(editor.register.contents, ["one"]);
+        press(&mut editor, "\x1bwwhcw");
+        assert_eq!(lines(&editor), ["uno two three", "last"]);
+        press(&mut editor, "\x1b$cw!");
+        assert_eq!(lines(&editor), ["uno two thre!", "last"]);
+        press(&mut editor, "\x1b0wC");
+        assert_eq!(editor.mode, Mode::Insert);
+        assert_eq!(lines(&editor), ["uno ", "last"]);
+        assert_eq!(editor.register.contents, ["two thre!"]);
+        assert!(!editor.register.linewise);
+    }
+
+    #[test]
+    fn delete_with_word_and_line_motions() {
+        let mut editor = editor("alpha beta gamma delta\nnext");
+        press(&mut editor, "dw");
+        assert_eq!(lines(&editor), ["beta gamma delta", "next"]);
+        assert_eq!(editor.register.contents, ["alpha "]);
+        assert!(!editor.register.linewise);
+        press(&mut editor, "2dw");
+        assert_eq!(lines(&editor), ["delta", "next"]);
+        assert_eq!(editor.register.contents, ["beta gamma "]);
+        press(&mut editor, "lld$");
+        assert_eq!(lines(&editor), ["de", "next"]);
+        assert_eq!(editor.register.contents, ["lta"]);
+        press(&mut editor, "jlld0");
+        assert_eq!(lines(&editor), ["de", "t"]);
+        assert_eq!(editor.register.contents, ["nex"]);
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+    }
+
+    #[test]
+    fn dot_repeats_last_change_at_cursor() {
+        let mut editor = editor("a\nb\nc\nd e f g");
+        press(&mut editor, "i> \x1bj0.");
+        assert_eq!(lines(&editor), ["> a", "> b", "c", "d e f g"]);
+        press(&mut editor, "jx.");
+        assert_eq!(lines(&editor), ["> a", "> b", "", "d e f g"]);
+        press(&mut editor, "j0dw.");
+        assert_eq!(lines(&editor), ["> a", "> b", "", "f g"]);
+        press(&mut editor, "kkk0rZj.");
+        assert_eq!(lines(&editor), ["Z a", "Z b", "", "f g"]);
+    }
+
+    #[test]
+    fn half_page_scrolling() {
+        let mut editor = sized_editor(&["abc"; 30].join("\n"), (20, 10));
+        press(&mut editor, "ll");
+        let mut moves = Vec::new();
+        for keys in ["\x04", "\x04", "\x04", "\x04", "\x04", "\x15", "\x15", "\x15", "\x15", "\x15"] {
+            press(&mut editor, keys);
+            refresh(&mut editor);
+            let cursor = &editor.output.cursor_controller;
+            moves.push((cursor.cursor_y, cursor.row_offset));
+        }
+        assert_eq!(
+            moves,
+            [(5, 5), (10, 10), (15, 15), (20, 20), (25, 20), (20, 15), (15, 10), (10, 5), (5, 0), (0, 0)]
+        );
+        assert_eq!(editor.output.cursor_position(), (0, 2));
+    }
+
+    #[test]
+    fn marks_jump_back_and_clamp() {
+        let mut editor = editor("one\ntwo\nthree\nfour");
+        press(&mut editor, "3jllmagg");
+        press(&mut editor, "x`a");
+        assert_eq!(editor.output.cursor_position(), (3, 2));
+        press(&mut editor, "`b");
+        assert_eq!(message(&editor), "E20: Mark not set");
+        assert_eq!(editor.output.cursor_position(), (3, 2));
+        press(&mut editor, "kdddd`a");
+        assert_eq!(lines(&editor), ["on", "two"]);
+        assert_eq!(editor.output.cursor_position(), (1, 2));
+        press(&mut editor, "0ddx`a");
+        assert_eq!(lines(&editor), ["n"]);
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+    }
+
+    #[test]
+    fn jump_list_walks_back_and_forward() {
+        let contents = (0..10).map(|n| format!("line {}", n)).collect::<Vec<_>>().join("\n");
+        let mut editor = editor(&contents);
+        press(&mut editor, "Ggg/line 5\r");
+        assert_eq!(editor.output.cursor_position(), (5, 0));
+        let walk = positions(&mut editor, "\x0f\x0f\x0f\t\t\t");
+        assert_eq!(walk, [(0, 0), (9, 0), (9, 0), (0, 0), (5, 0), (5, 0)]);
+        press(&mut editor, "\x0fj");
+        press(&mut editor, "G");
+        assert_eq!(positions(&mut editor, "\x0f\t\t"), [(1, 0), (9, 0), (9, 0)]);
+    }
+
+    #[test]
+    fn buffers_keep_their_own_state() {
+        let path = temp_path("second-buffer.txt");
+        fs::write(&path, "alpha\nbeta\ngamma\n").unwrap();
+        let mut editor = editor("first\nbuffer");
+        press(&mut editor, "jlllix\x1b");
+        press(&mut editor, &format!(":e {}\r", path.display()));
+        assert_eq!(lines(&editor), ["alpha", "beta", "gamma"]);
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+        press(&mut editor, "jjl");
+        let listing = format!("1#+ \"[No Name]\" | 2% \"{}\"", path.display());
+        press(&mut editor, ":ls\r");
+        assert_eq!(message(&editor), listing);
+        press(&mut editor, ":bn\r");
+        assert_eq!(lines(&editor), ["first", "bufxfer"]);
+        assert_eq!(editor.output.cursor_position(), (1, 3));
+        assert!(editor.output.editor_rows.dirty > 0);
+        press(&mut editor, ":bp\r");
+        assert_eq!(lines(&editor), ["alpha", "beta", "gamma"]);
+        assert_eq!(editor.output.cursor_position(), (2, 1));
+        assert_eq!(editor.output.editor_rows.dirty, 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn split_sizes_divide_space_between_panes() {
+        assert_eq!(split_sizes(22, 1), [22]);
+        assert_eq!(split_sizes(22, 2), [11, 10]);
+        assert_eq!(split_sizes(80, 3), [26, 26, 26]);
+        assert_eq!(split_sizes(10, 3), [3, 3, 2]);
+        assert_eq!(split_sizes(3, 3), [1, 0, 0]);
+    }
+
+    #[test]
+    fn split_window_lays_out_panes() {
+        let mut editor = editor("one\ntwo\nthree");
+        press(&mut editor, ":split\r");
+        let layout = |editor: &Editor| -> Vec<((usize, usize), (usize, usize))> {
+            editor.output.windows.iter().map(|window| (window.origin, window.size)).collect()
+        };
+        assert_eq!(layout(&editor), [((0, 0), (80, 11)), ((0, 12), (80, 10))]);
+        press(&mut editor, ":vsplit\r");
+        assert_eq!(message(&editor), "Mixed horizontal and vertical splits are not supported");
+        press(&mut editor, "j\x17j");
+        assert_eq!(editor.output.current_window, 1);
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+        press(&mut editor, "\x17k");
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+        press(&mut editor, ":close\r");
+        assert_eq!(layout(&editor), [((0, 0), (80, 22))]);
+        press(&mut editor, ":vsplit\r:vsplit\r");
+        let panes = [((0, 0), (26, 22)), ((27, 0), (26, 22)), ((54, 0), (26, 22))];
+        assert_eq!(layout(&editor), panes);
+    }
+
+    #[test]
+    fn steady_redraws_reuse_frame_buffer() {
+        let contents = ["\tfn main() { let long = \"x\"; } // é漢字"; 1000].join("\n");
+        let mut editor = editor(&contents);
+        let frame = |editor: &Editor| {
+            let contents = &editor.output.editor_contents;
+            let mut frames = [contents.content.as_ptr(), contents.previous_content.as_ptr()];
+            frames.sort();
+            (frames, contents.output.as_ptr(), contents.output.capacity())
+        };
+        refresh(&mut editor);
+        refresh(&mut editor);
+        let warm = frame(&editor);
+        for _ in 0..20 {
+            press(&mut editor, "\x04");
+            refresh(&mut editor);
+            assert_eq!(frame(&editor), warm);
+        }
+    }
+
+    #[test]
+    fn run_skips_redraw_until_state_changes() {
+        #[derive(Clone, Default)]
+        struct Written(Rc<RefCell<Vec<u8>>>);
+
+        impl Write for Written {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut editor = editor("one\ntwo");
+        let written = Written::default();
+        editor.output.editor_contents = EditorContents::with_writer(Box::new(written.clone()));
+        refresh(&mut editor);
+        assert!(!editor.output.needs_redraw);
+        written.0.borrow_mut().clear();
+        script(&mut editor, &[key('j')]);
+        assert!(editor.run().unwrap());
+        assert!(written.0.borrow().is_empty());
+        assert!(editor.output.needs_redraw);
+        script(&mut editor, &[key('k')]);
+        assert!(editor.run().unwrap());
+        assert!(!written.0.borrow().is_empty());
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+        refresh(&mut editor);
+        assert!(!editor.output.needs_redraw);
+        let expired = Instant::now().checked_sub(Duration::from_secs(6));
+        editor.output.status_message.set_time = expired;
+        written.0.borrow_mut().clear();
+        script(&mut editor, &[key('h')]);
+        editor.run().unwrap();
+        assert!(!written.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn write_changes_emits_only_changed_rows() {
+        let mut contents = EditorContents::new();
+        let frame = |contents: &mut EditorContents, rows: &[&str], cursor: (usize, usize)| {
+            for (y, row) in rows.iter().enumerate() {
+                contents.move_to(0, y);
+                contents.push_str(row);
+            }
+            contents.set_cursor(cursor.0, cursor.1);
+            contents.write_changes();
+            contents.output.clone()
+        };
+        let move_to = |x, y| {
+            let mut ansi = String::new();
+            cursor::MoveTo(x, y).write_ansi(&mut ansi).unwrap();
+            ansi
+        };
+        let first = frame(&mut contents, &["one", "two", "three"], (0, 0));
+        assert!(first.contains(&format!("{}one", move_to(0, 0))));
+        assert!(first.contains(&format!("{}three", move_to(0, 2))));
+        let second = frame(&mut contents, &["one", "2", "three"], (0, 0));
+        assert!(second.contains(&format!("{}2", move_to(0, 1))));
+        assert!(!second.contains("one") && !second.contains("three"));
+        let mut show = move_to(2, 1);
+        cursor::Show.write_ansi(&mut show).unwrap();
+        assert_eq!(frame(&mut contents, &["one", "2", "three"], (2, 1)), show);
+        contents.invalidate();
+        assert!(frame(&mut contents, &["one", "2", "three"], (2, 1)).contains("one"));
+    }
+
+    #[test]
+    fn stdin_contents_load_into_scratch_buffer() {
+        let input: &[u8] = b"piped\nout\xffput\n";
+        let editor_rows = EditorRows::from_reader(input).unwrap();
+        let rows: Vec<&str> =
+            editor_rows.row_contents.iter().map(|row| row.row_content.as_str()).collect();
+        assert_eq!(rows, ["piped", "out\u{fffd}put"]);
+        assert_eq!(editor_rows.filename, None);
+        assert_eq!(editor_rows.display_name(), "[stdin]");
+        let mut output = Output::with_size(editor_rows, Config::default(), (80, 22));
+        output.editor_contents = EditorContents::with_writer(Box::new(io::sink()));
+        let mut editor = Editor::with_output(output);
+        press(&mut editor, "\x13\x1b");
+        assert_eq!(message(&editor), "Save aborted");
+        let path = temp_path("stdin.txt");
+        press(&mut editor, &format!("\x13{}\r", path.display()));
+        assert_eq!(editor.output.editor_rows.filename.as_deref(), Some(path.as_path()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "piped\nout\u{fffd}put\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_only_buffer_rejects_edits() {
+        let mut editor = editor("one two\nthree");
+        press(&mut editor, ":view\r");
+        assert!(editor.output.editor_rows.read_only);
+        for keys in ["ixy\x1b", "x", "dd", "dw", "ohello\x1b", "p", "u", "J"] {
+            press(&mut editor, keys);
+            assert_eq!(lines(&editor), ["one two", "three"], "{:?}", keys);
+            assert_eq!(message(&editor), READ_ONLY_MESSAGE, "{:?}", keys);
+        }
+        assert_eq!(editor.output.editor_rows.dirty, 0);
+        press(&mut editor, "/thr\r");
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+        press(&mut editor, "l\x13");
+        assert_eq!(editor.output.cursor_position(), (1, 1));
+        assert_eq!(message(&editor), READ_ONLY_MESSAGE);
+    }
+
+    #[test]
+    fn goto_line_command_jumps_and_clamps() {
+        assert_eq!(Command::parse("42"), Some(Command::Goto(42)));
+        assert_eq!(Command::parse("abc"), None);
+        let mut editor = editor(&["  line"; 50].join("\n"));
+        press(&mut editor, ":30\r");
+        assert_eq!(editor.output.cursor_position(), (29, 0));
+        refresh(&mut editor);
+        let row_offset = editor.output.cursor_controller.row_offset;
+        assert!((8..=29).contains(&row_offset), "{}", row_offset);
+        press(&mut editor, ":1\r");
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+        press(&mut editor, ":999\r");
+        assert_eq!(editor.output.cursor_position(), (49, 0));
+        press(&mut editor, ":abc\r");
+        assert_eq!(message(&editor), "E492: Not an editor command: abc");
+        assert_eq!(editor.output.cursor_position(), (49, 0));
+        press(&mut editor, "\x0f");
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+    }
+
+    #[test]
+    fn substitute_on_current_line() {
+        let mut editor = editor("foo foo\nfoo");
+        press(&mut editor, ":s/foo/bar/\r");
+        assert_eq!(lines(&editor), ["bar foo", "foo"]);
+        assert!(editor.output.editor_rows.dirty > 0);
+        press(&mut editor, ":s/foo/baz/g\r");
+        assert_eq!(lines(&editor), ["bar baz", "foo"]);
+        press(&mut editor, ":s/qux/x/\r");
+        assert_eq!(message(&editor), "E486: Pattern not found: qux");
+        assert_eq!(lines(&editor), ["bar baz", "foo"]);
+        press(&mut editor, "uu");
+        assert_eq!(lines(&editor), ["foo foo", "foo"]);
+    }
+
+    #[test]
+    fn substitute_handles_escaped_delimiters() {
+        let mut editor = editor("a/b/c");
+        press(&mut editor, ":s/a\\/b/x\\/y/\r");
+        assert_eq!(lines(&editor), ["x/y/c"]);
+        press(&mut editor, ":s#/#|#g\r");
+        assert_eq!(lines(&editor), ["x|y|c"]);
+    }
+
+    #[test]
+    fn substitute_across_ranges() {
+        let mut editor = editor("foo\nbar\nfoo foo\nfoo");
+        press(&mut editor, ":%s/foo/x/g\r");
+        assert_eq!(lines(&editor), ["x", "bar", "x x", "x"]);
+        assert_eq!(message(&editor), "4 substitutions on 3 lines");
+        assert_eq!(editor.output.cursor_position(), (3, 0));
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["foo", "bar", "foo foo", "foo"]);
+        press(&mut editor, ":2,3s/foo/y/\r");
+        assert_eq!(lines(&editor), ["foo", "bar", "y foo", "foo"]);
+        press(&mut editor, ":3,1s/o/0/g\r");
+        assert_eq!(lines(&editor), ["f00", "bar", "y f00", "foo"]);
+        assert_eq!(message(&editor), "4 substitutions on 2 lines");
+        press(&mut editor, ":2,9s/a/b/\r");
+        assert_eq!(message(&editor), "E16: Invalid range");
+    }
+
+    #[test]
+    fn regex_search_and_substitute() {
+        let mut editor = configured_editor("key = value\nname = vim", "regex = true");
+        press(&mut editor, "/n\\w+\r");
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+        press(&mut editor, ":%s/(\\w+) = (\\w+)/\\2: \\1/\r");
+        assert_eq!(lines(&editor), ["value: key", "vim: name"]);
+        press(&mut editor, ":s/([/x/\r");
+        assert_eq!(message(&editor), "E383: Invalid search string: ([");
+        assert_eq!(lines(&editor), ["value: key", "vim: name"]);
+    }
+
+    #[test]
+    fn percent_jumps_between_nested_brackets() {
+        let mut editor = editor("fn f(a[0]) {\n  if (x) { y }\n}");
+        let pairs = [
+            ((0, 4), (0, 9)),
+            ((0, 6), (0, 8)),
+            ((0, 11), (2, 0)),
+            ((1, 5), (1, 7)),
+            ((1, 9), (1, 13))
+        ];
+        for (start, end) in pairs {
+            editor.output.set_cursor_position(start);
+            press(&mut editor, "%");
+            assert_eq!(editor.output.cursor_position(), end, "{:?}", start);
+            press(&mut editor, "%");
+            assert_eq!(editor.output.cursor_position(), start, "{:?}", end);
+        }
+        editor.output.set_cursor_position((0, 0));
+        press(&mut editor, "%");
+        assert_eq!(editor.output.cursor_position(), (0, 9));
+        editor.output.set_cursor_position((1, 6));
+        press(&mut editor, "%");
+        assert_eq!(editor.output.cursor_position(), (1, 5));
+    }
+
+    #[test]
+    fn percent_reports_unbalanced_brackets() {
+        let mut editor = editor("(()\nnone");
+        press(&mut editor, "%");
+        assert_eq!(message(&editor), "No matching bracket");
+        assert_eq!(editor.output.cursor_position(), (0, 0));
+        press(&mut editor, "j%");
+        assert_eq!(message(&editor), "No matching bracket");
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+    }
+
+    #[test]
+    fn cursor_bracket_partner_is_highlighted() {
+        let colored = |highlight: HighlightType, text: &str| {
+            style::SetForegroundColor(highlight.color()).to_string() + text
+        };
+        let mut output = Output::with_size(editor_rows("a(b[c]d)e\n{\n}"), Config::default(), (40, 4));
+        let cases = [
+            ((0, 1), Some((0, 7))),
+            ((0, 5), Some((0, 3))),
+            ((0, 0), None),
+            ((1, 0), Some((2, 0)))
+        ];
+        for (cursor, partner) in cases {
+            output.set_cursor_position(cursor);
+            output.draw_rows(Mode::Normal);
+            assert_eq!(output.bracket_match, partner, "{:?}", cursor);
+            output.editor_contents.write_changes();
+        }
+        output.set_cursor_position((0, 1));
+        output.draw_rows(Mode::Normal);
+        let row = drawn_rows(&output)[0].to_string();
+        let partner = colored(HighlightType::MatchBracket, ")");
+        assert!(row.contains(&(partner + &colored(HighlightType::Normal, "e"))));
+        assert!(!row.contains(&colored(HighlightType::MatchBracket, "(")));
+    }
+
+    #[test]
+    fn offscreen_bracket_partner_is_not_highlighted() {
+        let contents = format!("({})", "\n".repeat(5));
+        let mut output = Output::with_size(editor_rows(&contents), Config::default(), (40, 3));
+        output.draw_rows(Mode::Normal);
+        assert_eq!(output.bracket_match, None);
+        let highlight = style::SetForegroundColor(HighlightType::MatchBracket.color()).to_string();
+        assert!(!drawn_rows(&output)[0].contains(&highlight));
+    }
+
+    #[test]
+    fn file_info_reports_position_and_counts() {
+        let mut editor = editor("one\ntwo\nthree\nfour");
+        editor.output.editor_rows.set_filename(PathBuf::from("notes.txt"));
+        press(&mut editor, "jjll\x07");
+        let info = "\"notes.txt\" 4 lines, 19 characters --line 3 of 4 --75%-- col 3";
+        assert_eq!(message(&editor), info);
+        press(&mut editor, "x:info\r");
+        let info = "\"notes.txt\" [Modified] 4 lines, 18 characters --line 3 of 4 --75%-- col 3";
+        assert_eq!(message(&editor), info);
+    }
+
+    #[test]
+    fn file_info_handles_tiny_buffers() {
+        let mut solo = editor("solo");
+        press(&mut solo, "$:f\r");
+        let info = "\"[No Name]\" 1 lines, 5 characters --line 1 of 1 --100%-- col 4";
+        assert_eq!(message(&solo), info);
+        let mut empty = editor("");
+        press(&mut empty, "\x07");
+        assert_eq!(message(&empty), "\"[No Name]\" --No lines in buffer--");
+    }
+
+    #[test]
+    fn positions_persist_and_clamp_on_reopen() {
+        let state = temp_path("positions");
+        let (short, long) = (temp_path("short.txt"), temp_path("long.txt"));
+        fs::write(&short, "a\nbc\n").unwrap();
+        fs::write(&long, "one\ntwo\nthree\n").unwrap();
+        let mut positions = Positions::load_from(&state);
+        positions.record(fs::canonicalize(&short).unwrap(), (10, 7));
+        positions.record(fs::canonicalize(&long).unwrap(), (2, 3));
+        positions.save_to(&state).unwrap();
+        assert_eq!(Positions::load_from(&state), positions);
+        let mut editor = editor("");
+        editor.output.positions_file = Some(state.clone());
+        press(&mut editor, &format!(":e {}\r", short.display()));
+        assert_eq!(editor.output.cursor_position(), (1, 1));
+        press(&mut editor, &format!(":e {}\r", long.display()));
+        assert_eq!(editor.output.cursor_position(), (2, 3));
+        press(&mut editor, "k");
+        editor.output.remember_positions().unwrap();
+        let canonical = fs::canonicalize(&long).unwrap();
+        assert_eq!(Positions::load_from(&state).get(&canonical), Some((1, 2)));
+        for path in [&short, &long, &state] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn autosave_due_requires_idle_dirty_named_buffer() {
+        let path = temp_path("autosave.txt");
+        let mut editor = editor("abc");
+        let idle = || Instant::now() - Duration::from_secs(3);
+        press(&mut editor, "x");
+        editor.last_input = idle();
+        assert!(!editor.autosave_due());
+        editor.output.config.autosave = 2;
+        assert!(!editor.autosave_due());
+        editor.output.editor_rows.set_filename(path.clone());
+        assert!(editor.autosave_due());
+        editor.last_input = Instant::now();
+        assert!(!editor.autosave_due());
+        editor.last_input = idle();
+        editor.output.editor_rows.read_only = true;
+        assert!(!editor.autosave_due());
+        editor.output.editor_rows.read_only = false;
+        editor.autosaved_at = Some(Instant::now());
+        editor.output.autosave();
+        assert_eq!(message(&editor), "auto-saved");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "bc");
+        assert!(!editor.autosave_due());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_autosave_waits_for_next_edit() {
+        let mut editor = editor("a");
+        editor.output.config.autosave = 1;
+        editor.output.editor_rows.filename = Some(PathBuf::from("/nonexistent/dir/file.txt"));
+        press(&mut editor, "x");
+        editor.last_input = Instant::now() - Duration::from_secs(2);
+        assert!(editor.autosave_due());
+        editor.autosaved_at = Some(Instant::now());
+        editor.output.autosave();
+        assert!(editor.output.editor_rows.dirty > 0);
+        assert!(!editor.autosave_due());
+        editor.autosaved_at = Some(Instant::now() - Duration::from_secs(2));
+        assert!(editor.autosave_due());
+    }
+
+    #[test]
+    fn swap_file_is_offered_recovered_and_cleaned_up() {
+        let path = temp_path("recover.txt");
+        fs::write(&path, "saved\n").unwrap();
+        let mut original = editor("");
+        press(&mut original, &format!(":e {}\r", path.display()));
+        press(&mut original, "ounsaved\x1b");
+        original.output.write_swap_files();
+        assert_eq!(swap::read(&path).unwrap(), "saved\nunsaved");
+        let mut reopened = editor("");
+        press(&mut reopened, &format!(":e {}\r", path.display()));
+        let offer = format!(
+            "Found swap file \"{}\"; :recover to restore unsaved changes",
+            swap::path(&path).display()
+        );
+        assert_eq!(message(&reopened), offer);
+        press(&mut reopened, ":recover\r");
+        assert_eq!(lines(&reopened), ["saved", "unsaved"]);
+        press(&mut reopened, "\x13");
+        assert!(!swap::exists(&path));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "saved\nunsaved\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn autopairs_insert_and_step_over() {
+        let mut editor = configured_editor("", "autopairs = true");
+        press(&mut editor, "iif (x[0");
+        assert_eq!(lines(&editor), ["if (x[0])"]);
+        press(&mut editor, "]) {\"a");
+        assert_eq!(lines(&editor), ["if (x[0]) {\"a\"}"]);
+        press(&mut editor, "\"}");
+        assert_eq!(lines(&editor), ["if (x[0]) {\"a\"}"]);
+        assert_eq!(editor.output.cursor_position(), (0, 15));
+        press(&mut editor, " don'");
+        assert_eq!(lines(&editor), ["if (x[0]) {\"a\"} don'"]);
+    }
+
+    #[test]
+    fn autopairs_backspace_deletes_empty_pair() {
+        let mut editor = configured_editor("", "autopairs = true");
+        press(&mut editor, "i([\x7f");
+        assert_eq!(lines(&editor), ["()"]);
+        press(&mut editor, "\x7f");
+        assert_eq!(lines(&editor), [""]);
+        press(&mut editor, "(a\x7f\x7f");
+        assert_eq!(lines(&editor), [""]);
+        let mut plain = configured_editor("", "");
+        press(&mut plain, "i(\"");
+        assert_eq!(lines(&plain), ["(\""]);
+    }
+
+    #[test]
+    fn gcc_toggles_line_comment() {
+        let mut editor = editor("fn main() {\n    let x = 1;\n}");
+        editor.output.editor_rows.set_filename(PathBuf::from("main.rs"));
+        press(&mut editor, "jgcc");
+        assert_eq!(lines(&editor), ["fn main() {", "    // let x = 1;", "}"]);
+        press(&mut editor, "gcc");
+        assert_eq!(lines(&editor), ["fn main() {", "    let x = 1;", "}"]);
+        press(&mut editor, "2gcc");
+        assert_eq!(lines(&editor), ["fn main() {", "    // let x = 1;", "// }"]);
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["fn main() {", "    let x = 1;", "}"]);
+        let mut plain = configured_editor("text", "");
+        press(&mut plain, "gcc");
+        assert_eq!(message(&plain), "No comment syntax for text");
+    }
+
+    #[test]
+    fn visual_gc_comments_mixed_selection() {
+        let mut editor = editor("// a\nb\n\n  c");
+        editor.output.editor_rows.set_filename(PathBuf::from("main.rs"));
+        press(&mut editor, "VGgc");
+        assert_eq!(lines(&editor), ["// // a", "// b", "", "  // c"]);
+        press(&mut editor, "ggVjgc");
+        assert_eq!(lines(&editor), ["// a", "b", "", "  // c"]);
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["// // a", "// b", "", "  // c"]);
+    }
+
+    #[test]
+    fn gc_with_motion_comments_whole_lines() {
+        let mut editor = editor("let a = 1;\nlet b = 2;\nlet c = 3;");
+        editor.output.editor_rows.set_filename(PathBuf::from("main.rs"));
+        press(&mut editor, "wgcw");
+        assert_eq!(lines(&editor), ["// let a = 1;", "let b = 2;", "let c = 3;"]);
+        press(&mut editor, "jgc2$");
+        assert_eq!(lines(&editor), ["// let a = 1;", "// let b = 2;", "// let c = 3;"]);
+        press(&mut editor, "ggwgcw");
+        assert_eq!(lines(&editor), ["let a = 1;", "// let b = 2;", "// let c = 3;"]);
+    }
+
+    #[test]
+    fn shift_lines_indent_and_dedent() {
+        let config = "expand_tab = true\nshift_width = 4";
+        let mut editor = configured_editor("x = 1\n  y\n\tz", config);
+        press(&mut editor, "$>>");
+        assert_eq!(lines(&editor), ["    x = 1", "  y", "\tz"]);
+        assert_eq!(editor.output.cursor_position(), (0, 4));
+        press(&mut editor, "j<<");
+        assert_eq!(lines(&editor), ["    x = 1", "y", "\tz"]);
+        press(&mut editor, "<<");
+        assert_eq!(lines(&editor), ["    x = 1", "y", "\tz"]);
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+        press(&mut editor, "j<<");
+        assert_eq!(lines(&editor), ["    x = 1", "y", "z"]);
+        press(&mut editor, "gg>><<<<");
+        assert_eq!(lines(&editor), ["x = 1", "y", "z"]);
+    }
+
+    #[test]
+    fn visual_shift_indents_selection() {
+        let mut editor = configured_editor("a\n\nb\nc", "expand_tab = false");
+        press(&mut editor, "Vjj>");
+        assert_eq!(lines(&editor), ["\ta", "", "\tb", "c"]);
+        assert_eq!(editor.mode, Mode::Normal);
+        press(&mut editor, "jjVk<");
+        assert_eq!(lines(&editor), ["\ta", "", "b", "c"]);
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["\ta", "", "\tb", "c"]);
+    }
+
+    #[test]
+    fn shift_with_motion_shifts_whole_lines() {
+        let mut editor = configured_editor("a b\nc\nd", "expand_tab = true\nshift_width = 2");
+        press(&mut editor, "w>w");
+        assert_eq!(lines(&editor), ["  a b", "c", "d"]);
+        press(&mut editor, ">2$");
+        assert_eq!(lines(&editor), ["    a b", "  c", "d"]);
+        press(&mut editor, "<$");
+        assert_eq!(lines(&editor), ["  a b", "  c", "d"]);
+        assert_eq!(editor.register.contents, Vec::<String>::new());
+    }
+
+    #[test]
+    fn ctrl_caret_returns_to_alternate_buffer() {
+        let ctrl_caret = KeyEvent::new(KeyCode::Char('^'), event::KeyModifiers::CONTROL);
+        let (first, second) = (temp_path("alternate-a.txt"), temp_path("alternate-b.txt"));
+        fs::write(&first, "a\n").unwrap();
+        fs::write(&second, "b\n").unwrap();
+        let mut editor = editor("scratch");
+        press_keys(&mut editor, &[ctrl_caret]);
+        assert_eq!(message(&editor), "E23: No alternate file");
+        press(&mut editor, &format!(":e {}\r:e {}\r", first.display(), second.display()));
+        assert_eq!(lines(&editor), ["b"]);
+        for expected in ["a", "b", "a"] {
+            press_keys(&mut editor, &[ctrl_caret]);
+            assert_eq!(lines(&editor), [expected]);
+        }
+        press(&mut editor, ":bn\r");
+        assert_eq!(lines(&editor), ["b"]);
+        press_keys(&mut editor, &[ctrl_caret]);
+        assert_eq!(lines(&editor), ["a"]);
+        press(&mut editor, ":bp\r");
+        assert_eq!(lines(&editor), ["scratch"]);
+        press_keys(&mut editor, &[ctrl_caret]);
+        assert_eq!(lines(&editor), ["a"]);
+        for path in [&first, &second] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    const FOLDABLE: &str = "fn a() {\n    one\n\n    two\n}\nfn b() {\n    three\n}";
+
+    #[test]
+    fn fold_range_follows_indentation() {
+        let editor_rows = editor_rows(FOLDABLE);
+        let ranges: Vec<_> = (0..9).map(|row| editor_rows.fold_range(row)).collect();
+        let expected = [Some(0..4), None, None, None, None, Some(5..7), None, None, None];
+        assert_eq!(ranges, expected);
+    }
+
+    #[test]
+    fn folded_lines_are_skipped_and_summarised() {
+        let mut editor = editor(FOLDABLE);
+        press(&mut editor, "jza");
+        assert_eq!(message(&editor), "E490: No fold found");
+        press(&mut editor, "kza");
+        assert_eq!(positions(&mut editor, "jjkk"), [(4, 0), (5, 0), (4, 0), (0, 0)]);
+        editor.output.draw_rows(Mode::Normal);
+        let rows = drawn_rows(&editor.output);
+        assert!(rows[0].contains("+--  4 lines: fn a() {"));
+        assert!(rows[1].contains('}') && rows[2].contains("fn b() {"));
+        editor.output.editor_contents.write_changes();
+        press(&mut editor, "za");
+        assert_eq!(positions(&mut editor, "j"), [(1, 0)]);
+    }
+
+    #[test]
+    fn folds_shift_with_edits_elsewhere() {
+        let mut editor = editor(FOLDABLE);
+        press(&mut editor, "5jzaggOnew\x1b");
+        assert_eq!(editor.output.editor_rows.closed_fold(7), Some(&(6..8)));
+        press(&mut editor, "7Gx");
+        assert!(editor.output.editor_rows.folds.is_empty());
+    }
+
+    #[test]
+    fn list_render_marks_tabs_and_trailing_spaces() {
+        let editor_rows = editor_rows("a\tb  \n\tx");
+        let (render, colors) = editor_rows.list_render(0, &ListChars::default()).unwrap();
+        assert_eq!(render, "a\u{203a}      b\u{b7}\u{b7}");
+        let whitespace: Vec<usize> = (0..colors.len())
+            .filter(|&index| colors[index] == HighlightType::Whitespace)
+            .collect();
+        assert_eq!(whitespace, [1, 2, 3, 4, 5, 6, 7, 9, 10]);
+        let listchars = ListChars::parse("tab:>-,trail:~,eol:$").unwrap();
+        assert_eq!(editor_rows.list_render(0, &listchars).unwrap().0, "a>------b~~$");
+        assert_eq!(editor_rows.list_render(1, &listchars).unwrap().0, ">-------x$");
+    }
+
+    #[test]
+    fn list_mode_leaves_cursor_columns_alone() {
+        let colored = |highlight: HighlightType, text: &str| {
+            style::SetForegroundColor(highlight.color()).to_string() + text
+        };
+        let mut editor = editor("a\tb  ");
+        press(&mut editor, "$");
+        let screen_position = |editor: &mut Editor| {
+            refresh(editor);
+            let output = &editor.output;
+            output.cursor_controller.cursor_screen_position(&output.editor_rows)
+        };
+        let plain = screen_position(&mut editor);
+        press(&mut editor, ":set list\r");
+        editor.output.draw_rows(Mode::Normal);
+        let row = drawn_rows(&editor.output)[0].to_string();
+        assert!(row.contains(&colored(HighlightType::Whitespace, "\u{203a}      ")));
+        assert!(row.contains(&colored(HighlightType::Whitespace, "\u{b7}\u{b7}")));
+        editor.output.editor_contents.write_changes();
+        assert_eq!(screen_position(&mut editor), plain);
+        assert_eq!(plain, Some((10, 0)));
+    }
+
+    #[test]
+    fn cursor_line_background_follows_cursor_row() {
+        let cursor_line = style::SetBackgroundColor(CURSOR_LINE_BACKGROUND).to_string();
+        let mut editor = configured_editor("fn a\nfn b\nfn c", "cursor_line = true");
+        editor.output.editor_rows.set_filename(PathBuf::from("main.rs"));
+        let highlighted = |editor: &mut Editor| -> Vec<usize> {
+            editor.output.draw_rows(Mode::Normal);
+            let rows = drawn_rows(&editor.output)
+                .iter()
+                .enumerate()
+                .filter(|(_, row)| row.contains(&cursor_line))
+                .map(|(index, _)| index)
+                .collect();
+            editor.output.editor_contents.write_changes();
+            rows
+        };
+        assert_eq!(highlighted(&mut editor), [0]);
+        press(&mut editor, "jj");
+        assert_eq!(highlighted(&mut editor), [2]);
+        editor.output.draw_rows(Mode::Normal);
+        let row = drawn_rows(&editor.output)[2].to_string();
+        let keyword = style::SetForegroundColor(HighlightType::Keyword.color()).to_string();
+        assert!(row.contains(&format!("{}{}fn", cursor_line, keyword)), "{:?}", row);
+        editor.output.editor_contents.write_changes();
+        press(&mut editor, ":set nocursorline\r");
+        assert!(highlighted(&mut editor).is_empty());
+    }
+
+    #[test]
+    fn color_columns_account_for_gutter_and_offset() {
+        let contents = ["0123456789"; 3].join("\n");
+        let mut output = Output::with_size(editor_rows(&contents), Config::default(), (40, 3));
+        output.config.color_columns = vec![3, 8, 20];
+        let stripe = style::SetBackgroundColor(COLOR_COLUMN_BACKGROUND).to_string();
+        let stripes = |output: &mut Output| -> Vec<(u16, u16)> {
+            output.draw_rows(Mode::Normal);
+            let contents = &output.editor_contents;
+            let stripes = (0..contents.segments.len())
+                .filter_map(|index| segment_at(&contents.content, &contents.segments, index))
+                .filter(|(_, segment)| segment.starts_with(&stripe))
+                .map(|(position, _)| position)
+                .collect();
+            output.editor_contents.write_changes();
+            stripes
+        };
+        let first_row = [(2, 0), (7, 0), (19, 0)];
+        assert_eq!(stripes(&mut output)[..3], first_row);
+        assert_eq!(stripes(&mut output).len(), 9);
+        output.set_option("number");
+        let gutter = output.gutter_width() as u16;
+        let first_row = [(gutter + 2, 0), (gutter + 7, 0), (gutter + 19, 0)];
+        assert_eq!(stripes(&mut output)[..3], first_row);
+        output.cursor_controller.column_offset = 4;
+        let stripes = stripes(&mut output);
+        assert_eq!(stripes[..2], [(gutter + 3, 0), (gutter + 15, 0)]);
+        assert_eq!(stripes.len(), 6);
+    }
+
+    #[test]
+    fn wrapped_rows_map_to_visual_rows() {
+        let editor_rows = editor_rows(&format!("ab\n{}\n\n{}", "x".repeat(25), "y".repeat(20)));
+        let mut cursor = CursorController::new((10, 8));
+        cursor.wrap = true;
+        let heights: Vec<usize> = (0..4).map(|row| cursor.row_height(row, &editor_rows)).collect();
+        assert_eq!(heights, [1, 3, 1, 2]);
+        assert_eq!(cursor.screen_rows_between(0, 3, &editor_rows), 5);
+        (cursor.cursor_y, cursor.cursor_x) = (1, 23);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.cursor_sub_row(&editor_rows), (2, 3));
+        assert_eq!(cursor.cursor_screen_position(&editor_rows), Some((3, 3)));
+        assert_eq!(cursor.screen_to_buffer((3, 3), 0, &editor_rows), (1, 23));
+        assert_eq!(cursor.screen_to_buffer((5, 1), 0, &editor_rows), (1, 5));
+        assert_eq!(cursor.screen_to_buffer((4, 6), 0, &editor_rows), (3, 14));
+        (cursor.cursor_y, cursor.cursor_x) = (3, 19);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.cursor_screen_position(&editor_rows), Some((9, 6)));
+    }
+
+    #[test]
+    fn wrapped_line_is_drawn_across_rows() {
+        let contents = format!("{}\nend", "abcdefghij".repeat(2));
+        let mut output = Output::with_size(editor_rows(&contents), Config::default(), (8, 5));
+        output.set_option("wrap");
+        output.fit_window();
+        output.draw_rows(Mode::Normal);
+        let rows = drawn_rows(&output);
+        let starts = ["abcdefgh", "ijabcdef", "ghij", "end", "~"];
+        for (row, start) in rows.iter().zip(starts) {
+            assert!(row.contains(start), "{:?} {:?}", row, start);
+        }
+    }
+
+    #[test]
+    fn scroll_back_to_line_start_after_horizontal_scroll() {
+        let editor_rows = editor_rows(&"x".repeat(100));
+        let mut cursor = CursorController::new((20, 5));
+        cursor.move_cursor('$', &editor_rows);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.column_offset, 80);
+        cursor.move_cursor('0', &editor_rows);
+        cursor.scroll(&editor_rows);
+        assert_eq!((cursor.column_offset, cursor.row_offset), (0, 0));
+    }
+
+    #[test]
+    fn recorded_macro_replays_edits() {
+        let mut editor = editor("one\ntwo\nthree\nfour\nfive");
+        press(&mut editor, "qa0i- \x1bjq");
+        assert_eq!(editor.macros[&'a'], "0i- \x1bj".chars().map(key).collect::<Vec<_>>());
+        assert_eq!(lines(&editor), ["- one", "two", "three", "four", "five"]);
+        press(&mut editor, "@a");
+        assert_eq!(lines(&editor), ["- one", "- two", "three", "four", "five"]);
+        press(&mut editor, "2@a");
+        assert_eq!(lines(&editor), ["- one", "- two", "- three", "- four", "five"]);
+        press(&mut editor, "@b");
+        assert_eq!(message(&editor), "Register b is empty");
+    }
+
+    #[test]
+    fn macro_replays_prompts_from_queued_keys() {
+        let mut editor = editor("a a\nb a\nc");
+        press(&mut editor, "qs:s/a/x/g\rjq");
+        assert_eq!(editor.macros[&'s'], ":s/a/x/g\rj".chars().map(key).collect::<Vec<_>>());
+        press(&mut editor, "qt@sq");
+        assert_eq!(editor.macros[&'t'], "@s".chars().map(key).collect::<Vec<_>>());
+        assert_eq!(lines(&editor), ["x x", "b x", "c"]);
+        assert!(!editor.reader.has_queued());
+    }
+
+    #[test]
+    fn recursive_macro_stops_at_depth_limit() {
+        let mut editor = editor("x");
+        press(&mut editor, "qb0iy\x1b@bq");
+        editor.output.set_message("");
+        press(&mut editor, "@b");
+        assert_eq!(message(&editor), "E169: Command too recursive");
+        assert_eq!(lines(&editor)[0].len(), 2 + MAX_MACRO_DEPTH);
+        assert!(!editor.reader.has_queued());
+    }
+
+    #[test]
+    fn at_at_repeats_last_macro() {
+        let mut editor = editor("a\nb\nc\nd\ne\nf");
+        press(&mut editor, "qc0i*\x1bjq");
+        press(&mut editor, "@@");
+        assert_eq!(message(&editor), "E748: No previously used register");
+        press(&mut editor, "@c");
+        assert_eq!(lines(&editor)[..3], ["*a", "*b", "c"]);
+        press(&mut editor, "@@");
+        assert_eq!(lines(&editor)[..4], ["*a", "*b", "*c", "d"]);
+        press(&mut editor, "2@@");
+        assert_eq!(lines(&editor), ["*a", "*b", "*c", "*d", "*e", "f"]);
+        assert_eq!(editor.last_macro, Some('c'));
+    }
+
+    #[test]
+    fn save_trims_trailing_whitespace_only_on_disk() {
+        let path = temp_path("trim.txt");
+        let config = "trim_trailing_whitespace = true\nfinal_newline = true";
+        let mut editor = configured_editor("a  \n\tb\t \nc\n\n", config);
+        editor.output.editor_rows.set_filename(path.clone());
+        press(&mut editor, "jl\x13");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n\tb\nc\n");
+        assert_eq!(lines(&editor), ["a  ", "\tb\t ", "c", ""]);
+        assert_eq!(editor.output.cursor_position(), (1, 1));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_contents_options_apply_independently() {
+        let editor_rows = editor_rows("x \ny");
+        let mut config = Config::default();
+        assert_eq!(editor_rows.file_contents(&config), "x \ny");
+        config.trim_trailing_whitespace = true;
+        assert_eq!(editor_rows.file_contents(&config), "x\ny");
+        config.trim_trailing_whitespace = false;
+        config.final_newline = true;
+        assert_eq!(editor_rows.file_contents(&config), "x \ny\n");
+    }
+
+    #[test]
+    fn crlf_file_round_trips_and_converts() {
+        let path = temp_path("crlf.txt");
+        fs::write(&path, "\u{feff}one\r\ntwo\r\n").unwrap();
+        let mut editor = editor("");
+        press(&mut editor, &format!(":e {}\r", path.display()));
+        assert_eq!(lines(&editor), ["one", "two"]);
+        assert_eq!(editor.output.editor_rows.file_format, FileFormat::Dos);
+        assert!(editor.output.editor_rows.bom);
+        assert!(editor.output.status_info(Mode::Normal).0.contains(" [dos]"));
+        press(&mut editor, "x\x13");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\u{feff}ne\r\ntwo\r\n");
+        press(&mut editor, ":set ff=unix\r");
+        assert!(editor.output.editor_rows.dirty > 0);
+        assert!(!editor.output.status_info(Mode::Normal).0.contains(" [dos]"));
+        press(&mut editor, "\x13");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\u{feff}ne\ntwo\n");
+        press(&mut editor, ":set ff=mac\r");
+        assert_eq!(message(&editor), "E474: Invalid argument: ff=mac");
+        assert_eq!(editor_rows("").file_format, FileFormat::Unix);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_preserves_line_endings_and_final_newline() {
+        for contents in ["a\nb\n", "a\r\nb\r\n", "a\nb", "a\r\nb", "\u{feff}a\n", "a\r\nb\n", ""] {
+            assert_eq!(editor_rows(contents).file_contents(&Config::default()), contents);
+        }
+    }
+
+    #[test]
+    fn detect_file_format_from_every_line() {
+        assert_eq!(FileFormat::detect("a\r\nb\r\n"), FileFormat::Dos);
+        assert_eq!(FileFormat::detect("a\r\nb\nc\r\n"), FileFormat::Unix);
+        assert_eq!(FileFormat::detect("a"), FileFormat::Unix);
+        assert_eq!(lines(&editor("a\r\nb\n")), ["a\r", "b"]);
+    }
+
+    #[test]
+    fn goto_tag_and_pop_back() {
+        let mut editor = editor("  \nstruct Editor;\n\nfn main() {\n    Editor::new();\n}");
+        let ctrl_bracket = KeyEvent::new(KeyCode::Char(']'), event::KeyModifiers::CONTROL);
+        press_keys(&mut editor, &[ctrl_bracket]);
+        assert_eq!(message(&editor), "E349: No identifier under cursor");
+        press(&mut editor, "\x14");
+        assert_eq!(message(&editor), "E73: Tag stack empty");
+        press(&mut editor, "4jll");
+        editor.output.tag_stack.push((0, editor.output.cursor_position()));
+        let tags = tags::parse("main\tmain.rs\t/^fn main() {$/\nEditor\tmain.rs\t2\n");
+        editor.output.goto_tag(&tags::find(&tags, "main").unwrap().address);
+        assert_eq!(editor.output.cursor_position(), (3, 0));
+        editor.output.goto_tag(&tags::find(&tags, "Editor").unwrap().address);
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+        press(&mut editor, "\x14");
+        assert_eq!(editor.output.cursor_position(), (4, 2));
+    }
+
+    #[test]
+    fn help_buffer_opens_read_only_and_closes_with_q() {
+        let mut editor = editor("text");
+        press(&mut editor, ":help\r");
+        assert_eq!(editor.output.editor_rows.scratch_name, Some(HELP_NAME));
+        assert!(editor.output.editor_rows.read_only);
+        assert_eq!(lines(&editor)[0], "Key bindings (q to close)");
+        assert!(lines(&editor).iter().any(|line| line.ends_with("quit the editor")));
+        press(&mut editor, "x");
+        assert_eq!(message(&editor), READ_ONLY_MESSAGE);
+        press(&mut editor, "q");
+        assert_eq!(lines(&editor), ["text"]);
+        press_code(&mut editor, KeyCode::Backspace);
+        assert_eq!(editor.output.editor_rows.scratch_name, None);
+        press_code(&mut editor, KeyCode::F(1));
+        assert_eq!(editor.output.editor_rows.scratch_name, Some(HELP_NAME));
+        assert_eq!(editor.output.buffers.len(), 2);
+    }
+
+    #[test]
+    fn home_toggles_between_indent_and_column_zero() {
+        let mut editor = editor("    let x = 1;\n      ");
+        press(&mut editor, "$");
+        let homes: Vec<(usize, usize)> = (0..3)
+            .map(|_| {
+                press_code(&mut editor, KeyCode::Home);
+                editor.output.cursor_position()
+            })
+            .collect();
+        assert_eq!(homes, [(0, 4), (0, 0), (0, 4)]);
+        assert_eq!(positions(&mut editor, "0^0$^"), [(0, 0), (0, 4), (0, 0), (0, 13), (0, 4)]);
+        press(&mut editor, "j$");
+        press_code(&mut editor, KeyCode::Home);
+        assert_eq!(editor.output.cursor_position(), (1, 0));
+        press_code(&mut editor, KeyCode::Home);
+        assert_eq!(editor.output.cursor_position(), (1, 5));
+        press(&mut editor, "o\x1b0i  ");
+        press_code(&mut editor, KeyCode::Home);
+        assert_eq!(editor.output.cursor_position(), (2, 0));
+        press_code(&mut editor, KeyCode::Home);
+        assert_eq!(editor.output.cursor_position(), (2, 2));
+    }
+
+    const PARAGRAPHS: &str = "First para.\nstill first.\n\nSecond. Two sentences.\n\n\nThird";
+
+    #[test]
+    fn paragraph_motions_stop_at_blank_lines() {
+        let mut editor = editor(PARAGRAPHS);
+        assert_eq!(positions(&mut editor, "}}}}"), [(2, 0), (4, 0), (6, 4), (6, 4)]);
+        assert_eq!(positions(&mut editor, "{{{{"), [(5, 0), (2, 0), (0, 0), (0, 0)]);
+        press(&mut editor, "d}");
+        assert_eq!(lines(&editor)[..2], ["", "Second. Two sentences."]);
+    }
+
+    #[test]
+    fn sentence_motions_cross_lines() {
+        let mut editor = editor(PARAGRAPHS);
+        let forward = [(1, 0), (2, 0), (3, 0), (3, 8), (4, 0)];
+        assert_eq!(positions(&mut editor, ")))))"), forward);
+        assert_eq!(positions(&mut editor, "(((("), [(3, 8), (3, 0), (2, 0), (1, 0)]);
+        press(&mut editor, "G$");
+        assert_eq!(positions(&mut editor, ")"), [(6, 4)]);
+    }
+
+    #[test]
+    fn unknown_cursor_direction_is_ignored() {
+        let editor_rows = editor_rows("ab\ncd");
+        let mut cursor = CursorController::new((20, 5));
+        cursor.move_cursor('l', &editor_rows);
+        cursor.move_cursor('?', &editor_rows);
+        assert_eq!((cursor.cursor_y, cursor.cursor_x), (0, 1));
+    }
+
+    #[test]
+    fn text_object_ranges_for_words_and_quotes() {
+        let editor_rows = editor_rows("say \"hi there\" and \"bye\"\nx = \"a \\\"b\\\" c\";");
+        let range = |at: (usize, usize), object| {
+            editor_rows.text_object(at, object).map(|(start, end)| (start.1, end.1))
+        };
+        assert_eq!(range((0, 0), 'w'), Some((0, 3)));
+        assert_eq!(range((0, 2), 'w'), Some((0, 3)));
+        assert_eq!(range((0, 3), 'w'), Some((3, 4)));
+        assert_eq!(range((0, 9), 'w'), Some((8, 13)));
+        assert_eq!(range((0, 0), '"'), Some((5, 13)));
+        assert_eq!(range((0, 4), '"'), Some((5, 13)));
+        assert_eq!(range((0, 13), '"'), Some((5, 13)));
+        assert_eq!(range((0, 14), '"'), Some((20, 23)));
+        assert_eq!(range((0, 23), '"'), Some((20, 23)));
+        assert_eq!(range((0, 0), '\''), None);
+        assert_eq!(range((1, 6), '"'), Some((5, 14)));
+        assert_eq!(range((1, 16), '"'), None);
+    }
+
+    #[test]
+    fn operators_apply_to_text_objects() {
+        let mut editor = editor("call(\"old text\", word)");
+        press(&mut editor, "17ldiw");
+        assert_eq!(lines(&editor), ["call(\"old text\", )"]);
+        press(&mut editor, "07lci\"new\x1b");
+        assert_eq!(lines(&editor), ["call(\"new\", )"]);
+        assert_eq!(editor.mode, Mode::Normal);
+        press(&mut editor, "05lyi\"");
+        assert_eq!(editor.register.contents, ["new"]);
+    }
+
+    #[test]
+    fn disk_change_compares_stored_and_current_mtime() {
+        let path = temp_path("mtime.txt");
+        fs::write(&path, "a\n").unwrap();
+        let mut editor_rows = EditorRows::from_file(&path).unwrap();
+        assert_eq!(editor_rows.disk_change(), DiskChange::Unchanged);
+        editor_rows.mtime = Some(SystemTime::UNIX_EPOCH);
+        assert_eq!(editor_rows.disk_change(), DiskChange::Modified);
+        editor_rows.mtime = None;
+        assert_eq!(editor_rows.disk_change(), DiskChange::Unchanged);
+        fs::remove_file(&path).unwrap();
+        editor_rows.mtime = Some(SystemTime::UNIX_EPOCH);
+        assert_eq!(editor_rows.disk_change(), DiskChange::Deleted);
+        assert_eq!(EditorRows::new().disk_change(), DiskChange::Unchanged);
+    }
+
+    #[test]
+    fn external_change_warns_and_prompts_before_saving() {
+        let path = temp_path("external.txt");
+        fs::write(&path, "a\n").unwrap();
+        let mut editor = editor("");
+        press(&mut editor, &format!(":e {}\r", path.display()));
+        editor.output.editor_rows.mtime = Some(SystemTime::UNIX_EPOCH);
+        editor.output.check_disk_change();
+        let name = path.display();
+        let warning = format!("W11: Warning: File \"{}\" has changed since editing started", name);
+        assert_eq!(message(&editor), warning);
+        press(&mut editor, "x\x13n\r");
+        assert_eq!(message(&editor), "Save aborted");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n");
+        press(&mut editor, "\x13y\r");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\n");
+        assert_eq!(editor.output.editor_rows.disk_change(), DiskChange::Unchanged);
+        fs::remove_file(&path).unwrap();
+        editor.output.check_disk_change();
+        assert_eq!(message(&editor), format!("E211: File \"{}\" no longer available", name));
+    }
+
+    #[test]
+    fn scrolloff_keeps_margin_around_cursor() {
+        let editor_rows = editor_rows(&["x"; 30].join("\n"));
+        let mut cursor = CursorController::new((80, 10));
+        cursor.scroll_off = 3;
+        let mut offsets = Vec::new();
+        for _ in 0..29 {
+            cursor.move_cursor('j', &editor_rows);
+            cursor.scroll(&editor_rows);
+            offsets.push(cursor.row_offset);
+        }
+        let expected: Vec<usize> = (1..30).map(|row: usize| row.saturating_sub(6).min(20)).collect();
+        assert_eq!(offsets, expected);
+        for _ in 0..29 {
+            cursor.move_cursor('k', &editor_rows);
+            cursor.scroll(&editor_rows);
+            let margin = cursor.cursor_y.saturating_sub(cursor.row_offset);
+            assert!(margin >= cursor.cursor_y.min(3), "{} {}", cursor.cursor_y, cursor.row_offset);
+        }
+        assert_eq!((cursor.cursor_y, cursor.row_offset), (0, 0));
+        cursor.move_cursor('j', &editor_rows);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.row_offset, 0);
+    }
+
+    #[test]
+    fn scrolloff_is_clamped_for_small_windows() {
+        let editor_rows = editor_rows(&["x"; 30].join("\n"));
+        let mut cursor = CursorController::new((80, 4));
+        cursor.scroll_off = 10;
+        cursor.goto_row(10, &editor_rows);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.row_offset, 8);
+        let mut editor = editor("x");
+        press(&mut editor, ":set so=3\r");
+        assert_eq!(editor.output.config.scroll_off, 3);
+        press(&mut editor, ":set so=x\r");
+        assert_eq!(message(&editor), "E521: Number required after =: so=x");
+    }
+
+    #[test]
+    fn edit_bang_reloads_from_disk() {
+        let path = temp_path("reload.rs");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut editor = editor("");
+        press(&mut editor, &format!(":e {}\r", path.display()));
+        press(&mut editor, "Goextra\x1b");
+        assert_eq!(editor.output.cursor_position(), (3, 4));
+        press(&mut editor, ":e\r");
+        assert_eq!(message(&editor), "E37: No write since last change (add ! to override)");
+        assert_eq!(lines(&editor), ["one", "two", "three", "extra"]);
+        fs::write(&path, "fn a() {}\nfn b() {}\n").unwrap();
+        editor.output.editor_rows.set_filename(temp_path("reload.txt"));
+        editor.output.editor_rows.filename = Some(path.clone());
+        assert_eq!(editor.output.editor_rows.syntax.name(), "text");
+        press(&mut editor, ":e!\r");
+        assert_eq!(lines(&editor), ["fn a() {}", "fn b() {}"]);
+        assert_eq!(editor.output.editor_rows.dirty, 0);
+        assert_eq!(editor.output.cursor_position(), (1, 4));
+        assert_eq!(editor.output.editor_rows.syntax.name(), "rust");
+        assert_eq!(message(&editor), format!("\"{}\" 2L", path.display()));
+        press(&mut editor, "u");
+        assert_eq!(message(&editor), "Already at oldest change");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn block_covers_rectangle_between_anchor_and_cursor() {
+        let mut editor = editor("abcdef\nab\nabcdefgh");
+        press(&mut editor, "ll\x16jjh");
+        assert_eq!(editor.mode, Mode::VisualBlock);
+        assert_eq!(editor.output.block(), Some((0..3, 1..3)));
+        let selected: Vec<_> = (0..3)
+            .map(|row| editor.output.selected_columns(Mode::VisualBlock, row))
+            .collect();
+        assert_eq!(selected, [Some((1, 3)), Some((1, 2)), Some((1, 3))]);
+        press(&mut editor, "kkll");
+        assert_eq!(editor.output.block(), Some((0..1, 2..4)));
+    }
+
+    #[test]
+    fn block_delete_and_yank_on_ragged_lines() {
+        let mut editor = editor("abcdef\nab\nabcdefgh");
+        press(&mut editor, "l\x16jjllx");
+        assert_eq!(lines(&editor), ["aef", "a", "aefgh"]);
+        assert_eq!(editor.register.contents, ["bcd", "b", "bcd"]);
+        assert_eq!(editor.output.cursor_position(), (0, 1));
+        assert_eq!(editor.mode, Mode::Normal);
+        press(&mut editor, "u\x16jjly");
+        assert_eq!(editor.register.contents, ["bc", "b", "bc"]);
+        assert_eq!(lines(&editor), ["abcdef", "ab", "abcdefgh"]);
+    }
+
+    #[test]
+    fn block_insert_and_append_replicate_text() {
+        let mut editor = editor("abcdef\nab\nabcdefgh");
+        press(&mut editor, "ll\x16jjIX\x1b");
+        assert_eq!(lines(&editor), ["abXcdef", "abX", "abXcdefgh"]);
+        press(&mut editor, "u0l\x16jjlA|\x1b");
+        assert_eq!(lines(&editor), ["abc|def", "ab |", "abc|defgh"]);
+    }
+
+    #[test]
+    fn undo_block_insert_returns_to_block_start() {
+        let mut editor = editor("abc\nabc\nabc");
+        press(&mut editor, "jjl\x16kIx\x1b");
+        assert_eq!(lines(&editor), ["abc", "axbc", "axbc"]);
+        press(&mut editor, "Gu");
+        assert_eq!(lines(&editor), ["abc", "abc", "abc"]);
+        assert_eq!(editor.output.cursor_position(), (1, 1));
+    }
+
+    #[test]
+    fn ruler_shows_top_middle_bottom_and_all() {
+        let mut editor = configured_editor(&["x"; 100].join("\n"), "ruler = true");
+        let ruler = |editor: &mut Editor| {
+            refresh(editor);
+            editor.output.status_info(Mode::Normal).1
+        };
+        assert_eq!(ruler(&mut editor), "text | 1:1  Top");
+        press(&mut editor, "50G");
+        assert_eq!(ruler(&mut editor), "text | 50:1  49%");
+        press(&mut editor, "G");
+        assert_eq!(ruler(&mut editor), "text | 100:1  Bot");
+        press(&mut editor, "gg");
+        assert_eq!(ruler(&mut editor), "text | 1:1  Top");
+        let mut small = configured_editor("a\nb", "ruler = true");
+        assert_eq!(ruler(&mut small), "text | 1:1  All");
+        press(&mut small, ":set noruler\r");
+        assert_eq!(ruler(&mut small), "text | 1:1");
+    }
+
+    #[test]
+    fn tilde_toggles_case_and_advances() {
+        let mut editor = editor("aBc\u{e9}ß");
+        press(&mut editor, "~~");
+        assert_eq!(lines(&editor), ["Abc\u{e9}ß"]);
+        assert_eq!(editor.output.cursor_position(), (0, 2));
+        press(&mut editor, "5~");
+        assert_eq!(lines(&editor), ["AbC\u{c9}SS"]);
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["Abc\u{e9}ß"]);
+    }
+
+    #[test]
+    fn case_operators_with_motions_and_visual() {
+        let mut editor = editor("hello wide world\nnext");
+        press(&mut editor, "wgUiw");
+        assert_eq!(lines(&editor), ["hello WIDE world", "next"]);
+        press(&mut editor, "0gUw");
+        assert_eq!(lines(&editor), ["HELLO WIDE world", "next"]);
+        press(&mut editor, "guu");
+        assert_eq!(lines(&editor)[0], "hello wide world");
+        press(&mut editor, "$vbbU");
+        assert_eq!(lines(&editor)[0], "hello WIDE WORLD");
+        assert_eq!(editor.mode, Mode::Normal);
+        press(&mut editor, "Vju");
+        assert_eq!(lines(&editor), ["hello wide world", "next"]);
+        press(&mut editor, "u");
+        assert_eq!(lines(&editor), ["hello WIDE WORLD", "next"]);
+    }
 }
@@ -0,0 +1,93 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Positions {
+    entries: Vec<(PathBuf, (usize, usize))>
+}
+
+impl Positions {
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+            })
+            .map(|dir| dir.join("rusty-vim").join("positions"))
+    }
+
+    pub fn load_from(path: &Path) -> Self {
+        fs::read_to_string(path).map_or_else(|_| Self::default(), |contents| Self::parse(&contents))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let row = fields.next()?.parse().ok()?;
+                let column = fields.next()?.parse().ok()?;
+                Some((PathBuf::from(fields.next()?), (row, column)))
+            })
+            .take(MAX_ENTRIES)
+            .collect();
+        Self { entries }
+    }
+
+    pub fn get(&self, file: &Path) -> Option<(usize, usize)> {
+        self.entries
+            .iter()
+            .find(|(path, _)| path == file)
+            .map(|&(_, position)| position)
+    }
+
+    pub fn record(&mut self, file: PathBuf, position: (usize, usize)) {
+        self.entries.retain(|(path, _)| *path != file);
+        self.entries.insert(0, (file, position));
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents: String = self
+            .entries
+            .iter()
+            .map(|(file, (row, column))| format!("{}\t{}\t{}\n", row, column, file.display()))
+            .collect();
+        fs::write(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_skips_malformed_lines() {
+        let contents = "3\t4\t/tmp/a.txt\nbad line\n1\tx\t/tmp/b\n0\t0\t/tmp/with\ttab\n";
+        let positions = Positions::parse(contents);
+        assert_eq!(positions.get(Path::new("/tmp/a.txt")), Some((3, 4)));
+        assert_eq!(positions.get(Path::new("/tmp/b")), None);
+        assert_eq!(positions.get(Path::new("/tmp/with\ttab")), Some((0, 0)));
+    }
+
+    #[test]
+    fn record_moves_entry_to_front_and_caps_size() {
+        let mut positions = Positions::default();
+        for index in 0..MAX_ENTRIES + 5 {
+            positions.record(PathBuf::from(format!("/tmp/{}", index)), (index, 0));
+        }
+        assert_eq!(positions.entries.len(), MAX_ENTRIES);
+        assert_eq!(positions.get(Path::new("/tmp/4")), None);
+        assert_eq!(positions.get(Path::new("/tmp/5")), Some((5, 0)));
+        positions.record(PathBuf::from("/tmp/5"), (1, 2));
+        assert_eq!(positions.entries[0], (PathBuf::from("/tmp/5"), (1, 2)));
+        assert_eq!(positions.entries.len(), MAX_ENTRIES);
+    }
+}
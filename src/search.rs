@@ -0,0 +1,119 @@
+use std::ops::Range;
+use regex::Regex;
+
+pub enum Pattern {
+    Literal(String),
+    Regex(Regex)
+}
+
+impl Pattern {
+    pub fn new(query: &str, regex: bool) -> Result<Self, String> {
+        match regex {
+            true => Regex::new(query)
+                .map(Pattern::Regex)
+                .map_err(|_| format!("E383: Invalid search string: {}", query)),
+            false => Ok(Pattern::Literal(query.into()))
+        }
+    }
+
+    pub fn find_from(&self, text: &str, start: usize) -> Option<Range<usize>> {
+        match self {
+            Pattern::Literal(query) => text[start..]
+                .find(query.as_str())
+                .map(|index| start + index..start + index + query.len()),
+            Pattern::Regex(regex) => regex.find_at(text, start).map(|found| found.range())
+        }
+    }
+
+    pub fn rfind_before(&self, text: &str, end: usize) -> Option<Range<usize>> {
+        match self {
+            Pattern::Literal(query) => text[..end]
+                .rfind(query.as_str())
+                .map(|index| index..index + query.len()),
+            Pattern::Regex(regex) => regex
+                .find_iter(text)
+                .take_while(|found| found.start() < end)
+                .last()
+                .map(|found| found.range())
+        }
+    }
+
+    pub fn replacements(&self, text: &str, replacement: &str, global: bool) -> Vec<(Range<usize>, String)> {
+        let limit = if global { usize::MAX } else { 1 };
+        match self {
+            Pattern::Literal(query) => text
+                .match_indices(query.as_str())
+                .take(limit)
+                .map(|(index, found)| (index..index + found.len(), replacement.into()))
+                .collect(),
+            Pattern::Regex(regex) => {
+                let template = expand_backreferences(replacement);
+                regex
+                    .captures_iter(text)
+                    .take(limit)
+                    .map(|captures| {
+                        let mut expanded = String::new();
+                        captures.expand(&template, &mut expanded);
+                        (captures.get(0).map_or(0..0, |found| found.range()), expanded)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+fn expand_backreferences(replacement: &str) -> String {
+    let mut template = String::new();
+    let mut chars = replacement.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.peek()) {
+            ('\\', Some(digit)) if digit.is_ascii_digit() => {
+                template.push_str(&format!("${{{}}}", digit));
+                chars.next();
+            }
+            ('\\', Some('$')) => {
+                template.push_str("$$");
+                chars.next();
+            }
+            ('\\', Some(&escaped)) => {
+                template.push(escaped);
+                chars.next();
+            }
+            _ => template.push(ch)
+        }
+    }
+    template
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regex_patterns_find_matches() {
+        let pattern = Pattern::new(r"\d+", true).unwrap();
+        assert_eq!(pattern.find_from("a12 b345", 0), Some(1..3));
+        assert_eq!(pattern.find_from("a12 b345", 3), Some(5..8));
+        assert_eq!(pattern.rfind_before("a12 b345", 5), Some(1..3));
+        let literal = Pattern::new(r"\d+", false).unwrap();
+        assert_eq!(literal.find_from("a12 \\d+", 0), Some(4..7));
+    }
+
+    #[test]
+    fn replacements_expand_capture_groups() {
+        let pattern = Pattern::new(r"(\w+)=(\w+)", true).unwrap();
+        let text = "a=1 b=2";
+        assert_eq!(pattern.replacements(text, r"\2=\1", false), [(0..3, "1=a".into())]);
+        assert_eq!(
+            pattern.replacements(text, "$2:$1 \\$", true),
+            [(0..3, "1:a $".into()), (4..7, "2:b $".into())]
+        );
+    }
+
+    #[test]
+    fn invalid_regex_reports_error() {
+        let err = Pattern::new("(unclosed", true).err();
+        assert_eq!(err.as_deref(), Some("E383: Invalid search string: (unclosed"));
+        assert!(Pattern::new("(unclosed", false).is_ok());
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub fn path(file: &Path) -> PathBuf {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    file.with_file_name(format!(".{}.swp", name))
+}
+
+pub fn exists(file: &Path) -> bool {
+    path(file).is_file()
+}
+
+pub fn read(file: &Path) -> io::Result<String> {
+    fs::read_to_string(path(file))
+}
+
+pub fn write(file: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path(file), contents)
+}
+
+pub fn remove(file: &Path) -> io::Result<()> {
+    match fs::remove_file(path(file)) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_file_sits_next_to_hidden_name() {
+        assert_eq!(path(Path::new("/tmp/dir/notes.txt")), Path::new("/tmp/dir/.notes.txt.swp"));
+        assert_eq!(path(Path::new("notes")), Path::new(".notes.swp"));
+    }
+
+    #[test]
+    fn write_detect_and_remove_swap_file() {
+        let name = format!("rusty-vim-{}-swapped.txt", std::process::id());
+        let file = std::env::temp_dir().join(name);
+        assert!(!exists(&file));
+        write(&file, "unsaved\n").unwrap();
+        assert!(exists(&file));
+        assert_eq!(read(&file).unwrap(), "unsaved\n");
+        remove(&file).unwrap();
+        assert!(!exists(&file));
+        remove(&file).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+use std::path::Path;
+use crossterm::style::Color;
+
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        name: "rust",
+        comment: Some("//"),
+        strings: &['"'],
+        extensions: &["rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+            "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+            "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+            "super", "trait", "true", "type", "unsafe", "use", "where", "while"
+        ]
+    },
+    Language {
+        name: "c",
+        comment: Some("//"),
+        strings: &['"'],
+        extensions: &["c", "h"],
+        keywords: &[
+            "auto", "break", "case", "char", "const", "continue", "default", "do", "double",
+            "else", "enum", "extern", "float", "for", "goto", "if", "int", "long", "register",
+            "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+            "union", "unsigned", "void", "volatile", "while"
+        ]
+    },
+    Language {
+        name: "python",
+        comment: Some("#"),
+        strings: &['"', '\''],
+        extensions: &["py"],
+        keywords: &[
+            "and", "as", "assert", "break", "class", "continue", "def", "del", "elif", "else",
+            "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is",
+            "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True", "try",
+            "while", "with", "yield"
+        ]
+    }
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HighlightType {
+    Normal,
+    Number,
+    String,
+    Keyword,
+    Comment,
+    Match,
+    MatchBracket,
+    Fold,
+    Whitespace
+}
+
+impl HighlightType {
+    pub fn color(self) -> Color {
+        match self {
+            HighlightType::Normal => Color::Reset,
+            HighlightType::Number => Color::Cyan,
+            HighlightType::String => Color::Green,
+            HighlightType::Keyword => Color::Yellow,
+            HighlightType::Comment => Color::DarkGrey,
+            HighlightType::Match => Color::Blue,
+            HighlightType::MatchBracket => Color::Magenta,
+            HighlightType::Fold => Color::DarkCyan,
+            HighlightType::Whitespace => Color::DarkBlue
+        }
+    }
+}
+
+pub trait SyntaxHighlight {
+    fn name(&self) -> &str;
+
+    fn comment(&self) -> Option<&str>;
+
+    fn highlight(&self, render: &str) -> Vec<HighlightType>;
+}
+
+pub struct Language {
+    pub name: &'static str,
+    pub comment: Option<&'static str>,
+    pub strings: &'static [char],
+    pub extensions: &'static [&'static str],
+    pub keywords: &'static [&'static str]
+}
+
+impl SyntaxHighlight for Language {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn comment(&self) -> Option<&str> {
+        self.comment
+    }
+
+    fn highlight(&self, render: &str) -> Vec<HighlightType> {
+        highlight_with(render, self)
+    }
+}
+
+pub struct NoHighlight;
+
+impl SyntaxHighlight for NoHighlight {
+    fn name(&self) -> &str {
+        "text"
+    }
+
+    fn comment(&self) -> Option<&str> {
+        None
+    }
+
+    fn highlight(&self, render: &str) -> Vec<HighlightType> {
+        vec![HighlightType::Normal; render.chars().count()]
+    }
+}
+
+pub fn select(file: &Path) -> &'static dyn SyntaxHighlight {
+    let extension = file.extension().and_then(|extension| extension.to_str());
+    LANGUAGES
+        .iter()
+        .find(|language| extension.is_some_and(|extension| language.extensions.contains(&extension)))
+        .map_or(&NoHighlight, |language| language)
+}
+
+fn is_separator(ch: char) -> bool {
+    ch.is_whitespace() || (ch.is_ascii_punctuation() && ch != '_')
+}
+
+fn starts_with_at(chars: &[char], at: usize, pattern: &str) -> bool {
+    let end = at + pattern.chars().count();
+    end <= chars.len() && chars[at..end].iter().copied().eq(pattern.chars())
+}
+
+fn char_literal_len(chars: &[char], at: usize) -> Option<usize> {
+    match chars.get(at + 1..)? {
+        ['\\', _, '\'', ..] => Some(4),
+        [ch, '\'', ..] if *ch != '\'' => Some(3),
+        _ => None
+    }
+}
+
+fn highlight_with(render: &str, language: &Language) -> Vec<HighlightType> {
+    let chars: Vec<char> = render.chars().collect();
+    let mut highlight = vec![HighlightType::Normal; chars.len()];
+    let mut previous_separator = true;
+    let mut in_string = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let previous_highlight = match i {
+            0 => HighlightType::Normal,
+            _ => highlight[i - 1]
+        };
+        if let Some(delimiter) = in_string {
+            highlight[i] = HighlightType::String;
+            if ch == '\\' && i + 1 < chars.len() {
+                highlight[i + 1] = HighlightType::String;
+                i += 2;
+                continue;
+            }
+            in_string = Some(delimiter).filter(|&delimiter| ch != delimiter);
+            previous_separator = true;
+            i += 1;
+            continue;
+        }
+        if language.comment.is_some_and(|comment| starts_with_at(&chars, i, comment)) {
+            highlight[i..].fill(HighlightType::Comment);
+            break;
+        }
+        if language.strings.contains(&ch) {
+            highlight[i] = HighlightType::String;
+            in_string = Some(ch);
+            i += 1;
+            continue;
+        }
+        if let Some(len) = (ch == '\'').then(|| char_literal_len(&chars, i)).flatten() {
+            highlight[i..i + len].fill(HighlightType::String);
+            previous_separator = true;
+            i += len;
+            continue;
+        }
+        if (ch.is_ascii_digit() && (previous_separator || previous_highlight == HighlightType::Number))
+            || (ch == '.' && previous_highlight == HighlightType::Number)
+        {
+            highlight[i] = HighlightType::Number;
+            previous_separator = false;
+            i += 1;
+            continue;
+        }
+        if previous_separator {
+            let keyword = language.keywords.iter().find(|keyword| {
+                starts_with_at(&chars, i, keyword)
+                    && chars
+                        .get(i + keyword.chars().count())
+                        .is_none_or(|&next| is_separator(next))
+            });
+            if let Some(keyword) = keyword {
+                let end = i + keyword.chars().count();
+                highlight[i..end].fill(HighlightType::Keyword);
+                i = end;
+                previous_separator = false;
+                continue;
+            }
+        }
+        previous_separator = is_separator(ch);
+        i += 1;
+    }
+    highlight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight_as(name: &str, render: &str) -> Vec<HighlightType> {
+        LANGUAGES.iter().find(|language| language.name == name).unwrap().highlight(render)
+    }
+
+    fn classes(name: &str, render: &str) -> String {
+        highlight_as(name, render)
+            .into_iter()
+            .map(|highlight| match highlight {
+                HighlightType::Number => 'n',
+                HighlightType::String => 's',
+                HighlightType::Keyword => 'k',
+                HighlightType::Comment => 'c',
+                _ => '.'
+            })
+            .collect()
+    }
+
+    #[test]
+    fn highlight_numbers_and_strings() {
+        assert_eq!(classes("rust", r#"x = 42 + 3.5;"#), "....nn...nnn.");
+        assert_eq!(classes("rust", r#"a1 "x\"y" 7"#), "...ssssss.n");
+        assert_eq!(classes("rust", r#"let s = "open"#), "kkk.....sssss");
+        assert_eq!(classes("rust", "fnord fn"), "......kk");
+        assert_eq!(NoHighlight.highlight("fn 1"), [HighlightType::Normal; 4]);
+    }
+
+    #[test]
+    fn select_highlighter_by_extension() {
+        let names = ["main.rs", "lib.c", "util.h", "setup.py", "notes.txt", "Makefile"]
+            .map(|file| select(Path::new(file)).name().to_string());
+        assert_eq!(names, ["rust", "c", "c", "python", "text", "text"]);
+    }
+
+    #[test]
+    fn highlight_line_comments_outside_strings() {
+        assert_eq!(classes("rust", "// 1 fn"), "ccccccc");
+        assert_eq!(classes("rust", "fn // x"), "kk.cccc");
+        assert_eq!(classes("rust", r#""a//b" 1"#), "ssssss.n");
+        assert_eq!(classes("python", "# 'x'"), "ccccc");
+        assert_eq!(classes("python", "'a\"#b' #"), "ssssss.c");
+    }
+
+    #[test]
+    fn comment_marker_inside_single_quotes_is_string() {
+        let highlight = highlight_as("python", "x = '#' # note");
+        assert_eq!(highlight[4..7], [HighlightType::String; 3]);
+        assert_eq!(highlight[8], HighlightType::Comment);
+    }
+
+    #[test]
+    fn quote_char_literal_does_not_open_string() {
+        let highlight = highlight_as("rust", "let q = '\"'; 1");
+        assert_eq!(highlight[8..11], [HighlightType::String; 3]);
+        assert_eq!(highlight[13], HighlightType::Number);
+        assert_eq!(highlight_as("rust", "fn f<'a>(x: &'a str)")[18], HighlightType::Normal);
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FILE: &str = "tags";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Line(usize),
+    Pattern(String)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    pub file: PathBuf,
+    pub address: Address
+}
+
+impl Tag {
+    pub fn parse(line: &str) -> Option<Self> {
+        if line.starts_with("!_TAG_") {
+            return None;
+        }
+        let mut fields = line.splitn(3, '\t');
+        let name = fields.next().filter(|name| !name.is_empty())?;
+        let file = fields.next().filter(|file| !file.is_empty())?;
+        let address = fields.next()?;
+        let address = address.split_once(";\"").map_or(address, |(address, _)| address);
+        Some(Self {
+            name: name.into(),
+            file: PathBuf::from(file),
+            address: Address::parse(address)?
+        })
+    }
+}
+
+impl Address {
+    fn parse(address: &str) -> Option<Self> {
+        if let Ok(line) = address.parse() {
+            return Some(Address::Line(line));
+        }
+        let pattern = address
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+            .or_else(|| address.strip_prefix('?').and_then(|pattern| pattern.strip_suffix('?')))?;
+        let mut unescaped = String::new();
+        let mut chars = pattern.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => unescaped.extend(chars.next()),
+                ch => unescaped.push(ch)
+            }
+        }
+        Some(Address::Pattern(unescaped))
+    }
+
+    pub fn locate<'a>(&self, lines: impl IntoIterator<Item = &'a str>) -> Option<usize> {
+        match self {
+            Address::Line(line) => lines.into_iter().nth(line.checked_sub(1)?).map(|_| line - 1),
+            Address::Pattern(pattern) => {
+                let start = pattern.strip_prefix('^');
+                let pattern = start.unwrap_or(pattern);
+                let end = pattern.strip_suffix('$');
+                let text = end.unwrap_or(pattern);
+                lines.into_iter().position(|line| match (start.is_some(), end.is_some()) {
+                    (true, true) => line == text,
+                    (true, false) => line.starts_with(text),
+                    (false, true) => line.ends_with(text),
+                    (false, false) => line.contains(text)
+                })
+            }
+        }
+    }
+}
+
+pub fn load(path: &Path) -> io::Result<Vec<Tag>> {
+    Ok(parse(&fs::read_to_string(path)?))
+}
+
+pub fn parse(contents: &str) -> Vec<Tag> {
+    contents.lines().filter_map(Tag::parse).collect()
+}
+
+pub fn find<'a>(tags: &'a [Tag], name: &str) -> Option<&'a Tag> {
+    tags.iter().find(|tag| tag.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_standard_tag_lines() {
+        let contents = "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+            main\tsrc/main.rs\t/^fn main() {$/;\"\tf\n\
+            Editor\tsrc/main.rs\t3948;\"\ts\n\
+            slash\tlib.rs\t/a\\/b/\n\
+            back\tlib.rs\t?^back?\n\
+            broken\tlib.rs\n";
+        let tags = parse(contents);
+        let addresses: Vec<(&str, &Address)> =
+            tags.iter().map(|tag| (tag.name.as_str(), &tag.address)).collect();
+        assert_eq!(
+            addresses,
+            [
+                ("main", &Address::Pattern("^fn main() {$".into())),
+                ("Editor", &Address::Line(3948)),
+                ("slash", &Address::Pattern("a/b".into())),
+                ("back", &Address::Pattern("^back".into()))
+            ]
+        );
+        assert_eq!(tags[0].file, Path::new("src/main.rs"));
+        assert_eq!(find(&tags, "Editor").map(|tag| &tag.file), Some(&PathBuf::from("src/main.rs")));
+        assert_eq!(find(&tags, "missing"), None);
+    }
+
+    #[test]
+    fn locate_resolves_lines_and_anchored_patterns() {
+        let lines = ["use std::fs;", "fn main() {", "    main();", "}"];
+        let locate =
+            |address: &str| Address::parse(address).and_then(|address| address.locate(lines));
+        assert_eq!(locate("2"), Some(1));
+        assert_eq!(locate("9"), None);
+        assert_eq!(locate("0"), None);
+        assert_eq!(locate("/^fn main() {$/"), Some(1));
+        assert_eq!(locate("/main();/"), Some(2));
+        assert_eq!(locate("/^main/"), None);
+        assert_eq!(locate("/fs;$/"), Some(0));
+    }
+}